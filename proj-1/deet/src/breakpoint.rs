@@ -0,0 +1,200 @@
+use crate::dwarf_data::Type;
+use nix::unistd::Pid;
+use std::collections::HashMap;
+
+/// x86-64 has four debug registers (DR0-DR3) that can each watch one aligned region.
+pub const MAX_WATCHPOINTS: usize = 4;
+
+pub struct Breakpoint {
+    /// The number users refer to this breakpoint by. Ids are never reused.
+    pub id: usize,
+    pub addr: usize,
+    /// The byte that the 0xcc instruction replaced, valid while the breakpoint is inserted into
+    /// the inferior.
+    pub orig_byte: u8,
+    pub enabled: bool,
+    pub hit_count: usize,
+    /// Only stop if this expression is true (see expr::Condition).
+    pub condition: Option<String>,
+    /// Number of upcoming hits to continue through without stopping.
+    pub ignore_count: usize,
+    /// Deleted the first time it stops the inferior (`tbreak`).
+    pub temporary: bool,
+    /// Where the user asked for the breakpoint, e.g. `main` or `foo.c:12`, so that it can be
+    /// found again in a new program after an exec.
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// Stop when the value changes ("watch").
+    Write,
+    /// Stop when the value is read ("rwatch").
+    Read,
+    /// Stop when the value is read or written ("awatch").
+    Access,
+}
+
+/// The stack frame that a watched local variable lives in. Once the frame returns, the variable
+/// is gone and its stack slot gets reused.
+pub struct WatchScope {
+    /// The thread whose stack the frame is on.
+    pub tid: Pid,
+    /// The frame's CFA. The frame has returned once rsp is at or above it.
+    pub cfa: usize,
+    /// Where the frame returns to, which is when to check for that.
+    pub return_addr: usize,
+}
+
+pub struct Watchpoint {
+    /// Shares its numbering with breakpoints.
+    pub id: usize,
+    /// What the user asked to watch, e.g. a variable name.
+    pub expr: String,
+    pub addr: usize,
+    /// 1, 2, 4 or 8 bytes.
+    pub len: usize,
+    pub kind: WatchKind,
+    /// Used to format the watched value; None shows it as a raw number.
+    pub entity_type: Option<Type>,
+    /// The value as of the last time the watchpoint was checked.
+    pub old_value: Vec<u8>,
+    /// For a watchpoint on a local variable, the frame whose return deletes it.
+    pub scope: Option<WatchScope>,
+    pub enabled: bool,
+    pub hit_count: usize,
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Default::default()
+    }
+
+    /// Adds an enabled breakpoint at `addr` and returns it.
+    pub fn add(&mut self, addr: usize, orig_byte: u8) -> &mut Breakpoint {
+        self.list.push(Breakpoint {
+            id: self.next_id,
+            addr,
+            orig_byte,
+            enabled: true,
+            hit_count: 0,
+            condition: None,
+            ignore_count: 0,
+            temporary: false,
+            location: None,
+        });
+        self.next_id += 1;
+        self.list.last_mut().unwrap()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.list.iter().find(|bp| bp.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.list.iter_mut().find(|bp| bp.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Breakpoint> {
+        let index = self.list.iter().position(|bp| bp.id == id)?;
+        Some(self.list.remove(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Breakpoint> {
+        self.list.iter_mut()
+    }
+
+    pub fn reset_hit_counts(&mut self) {
+        for bp in self.list.iter_mut() {
+            bp.hit_count = 0;
+        }
+        for wp in self.watchpoints.iter_mut() {
+            wp.hit_count = 0;
+        }
+    }
+
+    /// Returns the ids of all breakpoints and watchpoints, in increasing order.
+    pub fn ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.list.iter().map(|bp| bp.id).collect();
+        ids.extend(self.watchpoints.iter().map(|wp| wp.id));
+        ids.sort();
+        ids
+    }
+
+    pub fn add_watchpoint(
+        &mut self,
+        expr: String,
+        addr: usize,
+        len: usize,
+        kind: WatchKind,
+        entity_type: Option<Type>,
+        scope: Option<WatchScope>,
+    ) -> &mut Watchpoint {
+        self.watchpoints.push(Watchpoint {
+            id: self.next_id,
+            expr,
+            addr,
+            len,
+            kind,
+            entity_type,
+            old_value: Vec::new(),
+            scope,
+            enabled: true,
+            hit_count: 0,
+        });
+        self.next_id += 1;
+        self.watchpoints.last_mut().unwrap()
+    }
+
+    pub fn get_watchpoint_mut(&mut self, id: usize) -> Option<&mut Watchpoint> {
+        self.watchpoints.iter_mut().find(|wp| wp.id == id)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        let index = self.watchpoints.iter().position(|wp| wp.id == id)?;
+        Some(self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    /// The enabled watchpoints, in the order they are assigned to debug registers: the Nth one
+    /// uses DR<N>.
+    pub fn enabled_watchpoints_mut(&mut self) -> impl Iterator<Item = &mut Watchpoint> {
+        self.watchpoints.iter_mut().filter(|wp| wp.enabled)
+    }
+
+    pub fn enabled_watchpoint_count(&self) -> usize {
+        self.watchpoints.iter().filter(|wp| wp.enabled).count()
+    }
+
+    /// Returns the original byte under `addr` if some enabled breakpoint has 0xcc inserted there.
+    /// Several breakpoints may share an address, in which case they share the byte too.
+    pub fn inserted_at(&self, addr: usize) -> Option<u8> {
+        self.list
+            .iter()
+            .find(|bp| bp.enabled && bp.addr == addr)
+            .map(|bp| bp.orig_byte)
+    }
+
+    /// Maps the address of every inserted breakpoint to the original byte under it.
+    pub fn inserted(&self) -> HashMap<usize, u8> {
+        self.list
+            .iter()
+            .filter(|bp| bp.enabled)
+            .map(|bp| (bp.addr, bp.orig_byte))
+            .collect()
+    }
+}
@@ -0,0 +1,501 @@
+//! Reading the state of a crashed process from its ELF core file.
+//!
+//! A core file has a PT_LOAD segment for every region of the process's memory, and PT_NOTE
+//! segments recording the registers of each thread (NT_PRSTATUS, followed by NT_FPREGSET), the
+//! signal that killed it (NT_SIGINFO), its command line (NT_PRPSINFO), and which files were
+//! mapped where (NT_FILE). Regions that are just a copy of a file, like the code of the executable
+//! and its libraries, are usually left out of the dump, so reads from them go to the file instead.
+
+use crate::inferior::Mapping;
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use object::{Object, ObjectSegment};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::mem::size_of;
+
+const PT_NOTE: u32 = 4;
+const ET_CORE: u16 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_FPREGSET: u32 = 2;
+const NT_PRPSINFO: u32 = 3;
+const NT_SIGINFO: u32 = 0x5349_4749;
+const NT_FILE: u32 = 0x4649_4c45;
+
+// Offsets into the x86-64 note structures (struct elf_prstatus and struct elf_prpsinfo)
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REG: usize = 112;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_PSARGS: usize = 56;
+const PRPSINFO_PSARGS_LEN: usize = 80;
+const SIGINFO_ADDR: usize = 16;
+
+/// A thread of the crashed process.
+pub struct CoreThread {
+    pub tid: Pid,
+    pub regs: user_regs_struct,
+    pub fpregs: Option<user_fpregs_struct>,
+}
+
+/// A PT_LOAD segment: memory at `addr` of which the first `file_size` bytes were dumped, at
+/// `offset` in the core file.
+struct Segment {
+    addr: usize,
+    size: usize,
+    offset: usize,
+    file_size: usize,
+}
+
+pub struct CoreDump {
+    data: memmap::Mmap,
+    segments: Vec<Segment>,
+    /// The id of the process that dumped core.
+    pub pid: Pid,
+    /// In the order the kernel wrote them, so the thread that crashed comes first.
+    pub threads: Vec<CoreThread>,
+    /// The signal that made the process dump core.
+    pub signal: Option<Signal>,
+    /// The address that a fault signal was about, if the core says.
+    pub fault_address: Option<usize>,
+    /// The process's command line, as far as the core records it.
+    pub command: String,
+    /// The file-backed regions of memory.
+    pub mappings: Vec<Mapping>,
+    /// The files in `mappings` that could be opened, for reading memory missing from the core.
+    files: HashMap<String, memmap::Mmap>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?) as usize)
+}
+
+/// Reads a C struct (such as user_regs_struct) laid out at `offset`.
+fn read_struct<T: Copy>(data: &[u8], offset: usize) -> Option<T> {
+    let bytes = data.get(offset..offset + size_of::<T>())?;
+    Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Returns the (type, descriptor) of each note in the PT_NOTE segments. object doesn't give us
+/// notes, so the program headers are read directly.
+fn notes(data: &[u8]) -> Option<Vec<(u32, &[u8])>> {
+    let phoff = read_u64(data, 0x20)?;
+    let phentsize = read_u16(data, 0x36)? as usize;
+    let phnum = read_u16(data, 0x38)? as usize;
+    let mut notes = Vec::new();
+    for index in 0..phnum {
+        let header = phoff + index * phentsize;
+        if read_u32(data, header)? != PT_NOTE {
+            continue;
+        }
+        let offset = read_u64(data, header + 8)?;
+        let size = read_u64(data, header + 32)?;
+        notes.extend(parse_note_segment(data.get(offset..offset + size)?)?);
+    }
+    Some(notes)
+}
+
+/// Splits the contents of a PT_NOTE segment into its notes. Each is a header of name size,
+/// descriptor size and type, then the name and descriptor, each padded to 4 bytes.
+fn parse_note_segment(segment: &[u8]) -> Option<Vec<(u32, &[u8])>> {
+    let mut notes = Vec::new();
+    let mut pos = 0;
+    while pos + 12 <= segment.len() {
+        let name_size = read_u32(segment, pos)? as usize;
+        let desc_size = read_u32(segment, pos + 4)? as usize;
+        let note_type = read_u32(segment, pos + 8)?;
+        let desc_start = pos + 12 + ((name_size + 3) & !3);
+        notes.push((note_type, segment.get(desc_start..desc_start + desc_size)?));
+        pos = desc_start + ((desc_size + 3) & !3);
+    }
+    Some(notes)
+}
+
+/// What the notes of a core file say about the process.
+struct Notes {
+    pid: Option<Pid>,
+    threads: Vec<CoreThread>,
+    signal: Option<Signal>,
+    fault_address: Option<usize>,
+    command: String,
+    mappings: Vec<Mapping>,
+}
+
+/// Interprets the notes of a core file, or returns None if any is too short for its type.
+fn parse_notes(notes: &[(u32, &[u8])]) -> Option<Notes> {
+    let mut parsed = Notes {
+        pid: None,
+        threads: Vec::new(),
+        signal: None,
+        fault_address: None,
+        command: String::new(),
+        mappings: Vec::new(),
+    };
+    for (note_type, desc) in notes {
+        match *note_type {
+            NT_PRSTATUS => {
+                let pid = read_u32(desc, PRSTATUS_PID)?;
+                let regs = read_struct(desc, PRSTATUS_REG)?;
+                if parsed.signal.is_none() {
+                    let cursig = read_u16(desc, PRSTATUS_CURSIG)?;
+                    parsed.signal = Signal::iterator().find(|s| *s as i32 == cursig as i32);
+                }
+                parsed.threads.push(CoreThread {
+                    tid: Pid::from_raw(pid as i32),
+                    regs,
+                    fpregs: None,
+                });
+            }
+            // Belongs to the thread whose NT_PRSTATUS came before it
+            NT_FPREGSET => {
+                if let Some(thread) = parsed.threads.last_mut() {
+                    thread.fpregs = read_struct(desc, 0);
+                }
+            }
+            NT_PRPSINFO => {
+                parsed.pid = Some(Pid::from_raw(read_u32(desc, PRPSINFO_PID)? as i32));
+                let args = desc.get(PRPSINFO_PSARGS..PRPSINFO_PSARGS + PRPSINFO_PSARGS_LEN)?;
+                let args = args.split(|byte| *byte == 0).next().unwrap_or(&[]);
+                parsed.command = String::from_utf8_lossy(args).trim().to_string();
+            }
+            NT_SIGINFO if parsed.fault_address.is_none() => {
+                parsed.fault_address = read_u64(desc, SIGINFO_ADDR);
+            }
+            NT_FILE => parsed.mappings = parse_file_note(desc)?,
+            _ => {}
+        }
+    }
+    Some(parsed)
+}
+
+/// Parses an NT_FILE note: a count and page size, then the start, end and offset (in pages) of
+/// each mapping, then their paths.
+fn parse_file_note(desc: &[u8]) -> Option<Vec<Mapping>> {
+    let count = read_u64(desc, 0)?;
+    let page_size = read_u64(desc, 8)?;
+    let mut paths = desc.get(16 + count * 24..)?.split(|byte| *byte == 0);
+    let mut mappings = Vec::with_capacity(count);
+    for index in 0..count {
+        let entry = 16 + index * 24;
+        mappings.push(Mapping {
+            start: read_u64(desc, entry)?,
+            end: read_u64(desc, entry + 8)?,
+            offset: read_u64(desc, entry + 16)? * page_size,
+            perms: String::new(),
+            path: String::from_utf8_lossy(paths.next()?).into_owned(),
+        });
+    }
+    Some(mappings)
+}
+
+impl CoreDump {
+    pub fn open(path: &str) -> Result<CoreDump, String> {
+        let file = fs::File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+        let data = unsafe { memmap::Mmap::map(&file) }.map_err(|err| err.to_string())?;
+        let not_core = || {
+            format!(
+                "\"{}\" is not a core dump: file format not recognized",
+                path
+            )
+        };
+        let object = object::File::parse(&*data).map_err(|_| not_core())?;
+        if read_u16(&data, 0x10) != Some(ET_CORE) {
+            return Err(not_core());
+        }
+        let segments = object
+            .segments()
+            .map(|segment| {
+                let (offset, file_size) = segment.file_range();
+                Segment {
+                    addr: segment.address() as usize,
+                    size: segment.size() as usize,
+                    offset: offset as usize,
+                    file_size: file_size as usize,
+                }
+            })
+            .collect();
+
+        let corrupt = || format!("Core file {} is truncated or corrupt", path);
+        let notes = notes(&data).ok_or_else(corrupt)?;
+        let notes = parse_notes(&notes).ok_or_else(corrupt)?;
+        if notes.threads.is_empty() {
+            return Err(format!("Core file {} has no threads", path));
+        }
+
+        let mut files = HashMap::new();
+        for mapping in &notes.mappings {
+            if files.contains_key(&mapping.path) {
+                continue;
+            }
+            let file = fs::File::open(&mapping.path);
+            if let Ok(mmap) = file.and_then(|file| unsafe { memmap::Mmap::map(&file) }) {
+                files.insert(mapping.path.clone(), mmap);
+            }
+        }
+        Ok(CoreDump {
+            data,
+            segments,
+            pid: notes.pid.unwrap_or(notes.threads[0].tid),
+            threads: notes.threads,
+            signal: notes.signal,
+            fault_address: notes.fault_address,
+            command: notes.command,
+            mappings: notes.mappings,
+            files,
+        })
+    }
+
+    /// Builds a core of one thread whose memory is the given (address, contents) regions, each
+    /// as if dumped whole in a PT_LOAD segment, for testing code that reads memory.
+    #[cfg(test)]
+    pub fn from_regions(regions: &[(usize, &[u8])]) -> CoreDump {
+        let len = regions.iter().map(|(_, bytes)| bytes.len()).sum::<usize>();
+        let mut data = memmap::MmapMut::map_anon(len.max(1)).unwrap();
+        let mut segments = Vec::new();
+        let mut offset = 0;
+        for (addr, bytes) in regions {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            segments.push(Segment {
+                addr: *addr,
+                size: bytes.len(),
+                offset,
+                file_size: bytes.len(),
+            });
+            offset += bytes.len();
+        }
+        let tid = Pid::from_raw(1);
+        CoreDump {
+            data: data.make_read_only().unwrap(),
+            segments,
+            pid: tid,
+            threads: vec![CoreThread {
+                tid,
+                regs: unsafe { std::mem::zeroed() },
+                fpregs: None,
+            }],
+            signal: None,
+            fault_address: None,
+            command: String::new(),
+            mappings: Vec::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn thread(&self, tid: Pid) -> Option<&CoreThread> {
+        self.threads.iter().find(|thread| thread.tid == tid)
+    }
+
+    /// Reads as much as possible of the `len` bytes at `addr`, up to the end of the region that
+    /// `addr` is in, from the core or else from the file mapped there.
+    fn read_chunk(&self, addr: usize, len: usize) -> Option<&[u8]> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.addr <= addr && addr < segment.addr + segment.size)?;
+        let index = addr - segment.addr;
+        if index < segment.file_size {
+            let len = len.min(segment.file_size - index);
+            return self
+                .data
+                .get(segment.offset + index..segment.offset + index + len);
+        }
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.start <= addr && addr < mapping.end)?;
+        let file = self.files.get(&mapping.path)?;
+        let start = mapping.offset + addr - mapping.start;
+        let len = len
+            .min(mapping.end - addr)
+            .min(file.len().checked_sub(start)?);
+        file.get(start..start + len)
+    }
+
+    /// Reads `len` bytes of the process's memory, or returns None if any of it wasn't saved.
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let chunk = self.read_chunk(addr + bytes.len(), len - bytes.len())?;
+            if chunk.is_empty() {
+                return None;
+            }
+            bytes.extend_from_slice(chunk);
+        }
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PRSTATUS_SIZE: usize = 336;
+    const PRPSINFO_SIZE: usize = 136;
+
+    /// Lays out a note the way the kernel writes it, with the name "CORE".
+    fn note(note_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut note = Vec::new();
+        note.extend_from_slice(&5u32.to_le_bytes());
+        note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        note.extend_from_slice(&note_type.to_le_bytes());
+        note.extend_from_slice(b"CORE\0\0\0\0");
+        note.extend_from_slice(desc);
+        note.resize((note.len() + 3) & !3, 0);
+        note
+    }
+
+    fn put(desc: &mut [u8], offset: usize, bytes: &[u8]) {
+        desc[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn prstatus(tid: u32, signal: u16, rip: u64, rsp: u64) -> Vec<u8> {
+        let mut desc = vec![0; PRSTATUS_SIZE];
+        put(&mut desc, PRSTATUS_CURSIG, &signal.to_le_bytes());
+        put(&mut desc, PRSTATUS_PID, &tid.to_le_bytes());
+        // rip and rsp are the 17th and 20th registers of user_regs_struct
+        put(&mut desc, PRSTATUS_REG + 16 * 8, &rip.to_le_bytes());
+        put(&mut desc, PRSTATUS_REG + 19 * 8, &rsp.to_le_bytes());
+        desc
+    }
+
+    fn parse(segment: &[u8]) -> Notes {
+        parse_notes(&parse_note_segment(segment).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_note_segment() {
+        let mut segment = note(7, b"abcde");
+        segment.extend(note(8, b""));
+        segment.extend(note(9, b"xyzw"));
+        let notes = parse_note_segment(&segment).unwrap();
+        assert_eq!(
+            notes,
+            vec![(7, &b"abcde"[..]), (8, &b""[..]), (9, &b"xyzw"[..])]
+        );
+        // A descriptor running past the end of the segment
+        assert_eq!(parse_note_segment(&segment[..segment.len() - 4]), None);
+        assert_eq!(parse_note_segment(&[]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_prstatus() {
+        let mut segment = note(NT_PRSTATUS, &prstatus(1234, 11, 0x401136, 0x7ffe0000));
+        segment.extend(note(NT_FPREGSET, &[0; size_of::<user_fpregs_struct>()]));
+        segment.extend(note(NT_PRSTATUS, &prstatus(1235, 0, 0x401200, 0x7f000000)));
+        let notes = parse(&segment);
+        assert_eq!(notes.signal, Some(Signal::SIGSEGV));
+        assert_eq!(notes.threads.len(), 2);
+        let (crashed, other) = (&notes.threads[0], &notes.threads[1]);
+        assert_eq!(crashed.tid, Pid::from_raw(1234));
+        assert_eq!(crashed.regs.rip, 0x401136);
+        assert_eq!(crashed.regs.rsp, 0x7ffe0000);
+        assert!(crashed.fpregs.is_some());
+        assert_eq!(other.tid, Pid::from_raw(1235));
+        assert_eq!(other.regs.rip, 0x401200);
+        assert_eq!(other.regs.rsp, 0x7f000000);
+        assert!(other.fpregs.is_none());
+        // No pid of its own without NT_PRPSINFO
+        assert_eq!(notes.pid, None);
+
+        let truncated = note(
+            NT_PRSTATUS,
+            &prstatus(1234, 11, 0, 0)[..PRSTATUS_SIZE - 100],
+        );
+        assert!(parse_notes(&parse_note_segment(&truncated).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_prpsinfo_and_siginfo() {
+        let mut prpsinfo = vec![0; PRPSINFO_SIZE];
+        put(&mut prpsinfo, PRPSINFO_PID, &1200u32.to_le_bytes());
+        put(&mut prpsinfo, PRPSINFO_PSARGS, b"./crashes 3 ");
+        let mut siginfo = vec![0; 128];
+        put(&mut siginfo, 0, &11u32.to_le_bytes());
+        put(&mut siginfo, SIGINFO_ADDR, &0xdead_beefu64.to_le_bytes());
+        let mut segment = note(NT_PRSTATUS, &prstatus(1234, 11, 0, 0));
+        segment.extend(note(NT_PRPSINFO, &prpsinfo));
+        segment.extend(note(NT_SIGINFO, &siginfo));
+        let notes = parse(&segment);
+        assert_eq!(notes.pid, Some(Pid::from_raw(1200)));
+        assert_eq!(notes.command, "./crashes 3");
+        assert_eq!(notes.fault_address, Some(0xdead_beef));
+    }
+
+    #[test]
+    fn test_file_note() {
+        let mut desc = Vec::new();
+        for value in &[2, 0x1000, 0x400000, 0x401000, 0, 0x7f0000, 0x7f3000, 3] {
+            desc.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+        desc.extend_from_slice(b"/tmp/prog\0/lib/libc.so.6\0");
+        let notes = parse(&note(NT_FILE, &desc));
+        assert_eq!(notes.mappings.len(), 2);
+        let libc = &notes.mappings[1];
+        assert_eq!((libc.start, libc.end), (0x7f0000, 0x7f3000));
+        // Offsets are given in pages
+        assert_eq!(libc.offset, 0x3000);
+        assert_eq!(libc.path, "/lib/libc.so.6");
+        assert_eq!(notes.mappings[0].path, "/tmp/prog");
+        // Cut off in the paths, or in the table before them
+        for len in &[16 + 2 * 24, 40] {
+            let truncated = note(NT_FILE, &desc[..*len]);
+            assert!(parse_notes(&parse_note_segment(&truncated).unwrap()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_read_across_segments() {
+        let first: Vec<u8> = (0..0x100).map(|byte| byte as u8).collect();
+        let second = vec![0xaa; 0x80];
+        let mut core = CoreDump::from_regions(&[(0x1000, &first), (0x1100, &second)]);
+        assert_eq!(core.read_bytes(0x1000, 4), Some(vec![0, 1, 2, 3]));
+        // Straddling the boundary between the two segments
+        assert_eq!(
+            core.read_bytes(0x10fe, 4),
+            Some(vec![0xfe, 0xff, 0xaa, 0xaa])
+        );
+        assert_eq!(
+            core.read_bytes(0x1000, 0x180).map(|bytes| bytes.len()),
+            Some(0x180)
+        );
+        // Outside every segment, or running off the end of the last
+        assert_eq!(core.read_bytes(0xfff, 2), None);
+        assert_eq!(core.read_bytes(0x1180, 1), None);
+        assert_eq!(core.read_bytes(0x117f, 2), None);
+        assert_eq!(core.read_bytes(0x5000, 8), None);
+
+        // Memory that wasn't dumped comes from the file mapped there, if it can be read
+        core.segments[1].size = 0x100;
+        assert_eq!(core.read_bytes(0x117f, 2), None);
+        core.mappings.push(Mapping {
+            start: 0x1100,
+            end: 0x1200,
+            offset: 0x10,
+            perms: String::new(),
+            path: "/lib/libfake.so".to_string(),
+        });
+        let mut file = memmap::MmapMut::map_anon(0x200).unwrap();
+        file[0x90..0x92].copy_from_slice(&[1, 2]);
+        core.files.insert(
+            "/lib/libfake.so".to_string(),
+            file.make_read_only().unwrap(),
+        );
+        assert_eq!(core.read_bytes(0x117f, 3), Some(vec![0xaa, 1, 2]));
+        assert_eq!(core.read_bytes(0x11fc, 4), Some(vec![0; 4]));
+        assert_eq!(core.read_bytes(0x11fc, 5), None);
+    }
+}
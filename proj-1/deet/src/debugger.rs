@@ -1,17 +1,95 @@
 use std::collections::HashMap;
-use crate::debugger_command::DebuggerCommand;
+use std::mem::size_of;
+use crate::debugger_command::{DebuggerCommand, ExamineSpec, FollowForkMode};
 use crate::inferior::{Inferior, self};
 use rustyline::error::ReadlineError;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use rustyline::Editor;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use regex::Regex;
+use iced_x86::{Decoder, DecoderOptions, Formatter, GasFormatter};
+use crate::dwarf_data::{
+    DwarfData, Encoding, Error as DwarfError, Line, Location, Type, TypeKind, Variable,
+};
+use crate::eval::Evaluator;
+use crate::expr::{self, Expr};
+use crate::breakpoint::{Breakpoints, WatchKind, WatchScope, MAX_WATCHPOINTS};
+use crate::unwind::{self, Frame};
+use crate::location;
+use crate::signals::{self, SignalAction, SignalTable};
+use crate::value;
 
 pub struct Debugger {
     target: String,
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    /// Processes forked by the inferior that we didn't follow, kept stopped because
+    /// detach-on-fork is off. The first one is switched to when the inferior exits.
+    held: Vec<Inferior>,
+    follow_fork_mode: FollowForkMode,
+    detach_on_fork: bool,
+    /// Whether to stop for, report and pass on each signal the inferior receives.
+    signals: SignalTable,
     debug_data: DwarfData,
-    breakpoints: HashMap<usize, u8>,    // addr -> orig_byte
+    breakpoints: Breakpoints,
+    /// Whether the last stop was caused by a watchpoint rather than a breakpoint or signal.
+    watchpoint_hit: bool,
+    /// The breakpoints that caused the last stop: those at the stop whose condition held and
+    /// which weren't being ignored.
+    breakpoints_hit: Vec<usize>,
+    /// The file and next line for a plain `list` to show. Cleared whenever the inferior stops, so
+    /// that the next listing is centered on the new location.
+    list_position: Option<(String, usize)>,
+    /// The stack as of the last stop, innermost frame first, ending at main.
+    frames: Vec<Frame>,
+    /// Index into `frames` of the frame that print, list, etc. look at.
+    selected_frame: usize,
+    /// The unwinding information of the inferior's executable and libraries, once read.
+    modules: unwind::ModuleCache,
+}
+
+/// Number of source lines shown by `list`.
+const LIST_SIZE: usize = 10;
+
+/// Number of frames a crash report shows. After a stack overflow there can be thousands, and the
+/// innermost ones are what matter.
+const CRASH_BACKTRACE_SIZE: usize = 20;
+
+/// The most memory `x` reads at once, so that a mistyped count can't ask for gigabytes.
+const MAX_EXAMINE_SIZE: usize = 64 * 1024;
+
+/// Writes a C declaration of `name` with the given type name, e.g. "char *s", "int a[4]" or
+/// "int (*f)()".
+fn declaration(type_name: &str, name: &str) -> String {
+    if let Some(index) = type_name.find("(*)") {
+        return format!(
+            "{}(*{}){}",
+            &type_name[..index],
+            name,
+            &type_name[index + 3..]
+        );
+    }
+    match type_name.find(" [") {
+        // The element type may need the name attached without a space too, as in "char *a[2]"
+        Some(index) => declaration(
+            &type_name[..index],
+            &format!("{}{}", name, &type_name[index + 1..]),
+        ),
+        None if type_name.ends_with('*') => format!("{}{}", type_name, name),
+        None => format!("{} {}", type_name, name),
+    }
+}
+
+/// Returns the first `size` bytes of a floating-point register, given as the 32-bit words that
+/// `user_fpregs_struct` stores it in.
+fn fp_register_bytes(words: &[u32], size: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .collect();
+    bytes.truncate(size);
+    bytes
 }
 
 fn parse_address(addr: &str) -> Option<usize> {
@@ -49,124 +127,2075 @@ impl Debugger {
             history_path,
             readline,
             inferior: None,
+            held: Vec::new(),
+            follow_fork_mode: FollowForkMode::Parent,
+            detach_on_fork: true,
+            signals: SignalTable::new(),
             debug_data,
-            breakpoints: HashMap::new(),
+            breakpoints: Breakpoints::new(),
+            watchpoint_hit: false,
+            breakpoints_hit: Vec::new(),
+            list_position: None,
+            frames: Vec::new(),
+            selected_frame: 0,
+            modules: unwind::ModuleCache::new(),
         }
     }
 
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
+            let command = self.get_next_command();
+            if self.examining_core() && command.needs_process() {
+                println!("The program is not being run.");
+                continue;
+            }
+            match command {
                 DebuggerCommand::Run(args) => {
                     if let Some(inf) = self.inferior.as_mut() {
-                        inf.kill(); // you pause an inferior using ctrl+c, then type run
+                        if inf.core().is_none() {
+                            inf.kill(); // you pause an inferior using ctrl+c, then type run
+                        }
                         self.inferior = None;
-                    } 
-                    if let Some(mut inferior) = Inferior::new(&self.target, &args) {
+                    }
+                    self.release_held();
+                    if let Some(inferior) = Inferior::new(&self.target, &args) {
                         // Create the inferior
-                        for (addr, orig_byte) in &mut self.breakpoints {    // set breakpoints
-                            *orig_byte = inferior.write_byte(*addr, 0xcc).unwrap();
-                        }
-
                         self.inferior = Some(inferior);
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                        let inf = self.inferior.as_mut().unwrap();
-                        if let Ok(status) = inf.cont(&self.breakpoints) {
-                            match status {
-                                inferior::Status::Exited(ecode) => {
-                                    println!("Child exited (status {})", ecode);
-                                    self.inferior = None;
-                                }
-                                inferior::Status::Signaled(signal) => {
-                                    println!("Child exited (signal {})", signal);
-                                    self.inferior = None;
-                                }
-                                inferior::Status::Stopped(signal, ip) => {
-                                    if let Some(line) = self.debug_data.get_line_from_addr(ip) {
-                                        println!("Child stopped (signal {})", signal);
-                                        println!("Stopped at {}", line);
-                                    }
-                                }
-                            }
+                        self.modules = unwind::ModuleCache::new();
+                        self.breakpoints.reset_hit_counts();
+                        if let Err(err) = self.insert_breakpoints() {
+                            println!("Cannot insert breakpoints: {}", err);
                         }
+                        self.update_debug_registers();
+                        let status = self.resume(&[]);
+                        self.report_status(status);
                     } else {
                         println!("Error starting subprocess");
                     }
                 }
+                DebuggerCommand::Attach(pid) => {
+                    if self.inferior.is_some() {
+                        println!("A program is being debugged already. Kill or detach it first.");
+                    } else {
+                        self.attach(Pid::from_raw(pid));
+                    }
+                }
+                DebuggerCommand::Detach => {
+                    if self.inferior.is_some() {
+                        self.detach();
+                    } else {
+                        println!("The program is not being run.");
+                    }
+                }
                 DebuggerCommand::Quit => {
-                    if let Some(inf) = self.inferior.as_mut() {
-                        inf.kill(); // if you exit DEET while a process is paused
+                    match self.inferior.as_mut() {
+                        Some(inf) if inf.core().is_some() => {}
+                        // Don't kill a process we didn't start
+                        Some(inf) if inf.is_attached() => self.detach(),
+                        Some(inf) => inf.kill(), // if you exit DEET while a process is paused
+                        None => {}
                     }
+                    self.release_held();
                     return;
                 }
                 DebuggerCommand::Continue => {
-                    if let Some(inf) = self.inferior.as_mut() {
-                        // inf.cont(&self.breakpoints).unwrap();
-                        if let Ok(status) = inf.cont(&self.breakpoints) {
-                            match status {
-                                inferior::Status::Exited(ecode) => {
-                                    println!("Child exited (status {})", ecode);
-                                    self.inferior = None;
-                                },
-                                inferior::Status::Signaled(signal) => {
-                                    println!("Child stopped (signal {})", signal);
-                                    self.inferior = None;
-                                },
-                                inferior::Status::Stopped(signal, ip) => {
-                                    if let Some(line) = self.debug_data.get_line_from_addr(ip) {
-                                        println!("Child stopped (signal {})", signal);
-                                        println!("Stopped at {}", line);
-                                    }
-                                }
-                            }
-                        }
+                    if self.inferior.is_some() {
+                        let status = self.resume(&[]);
+                        self.report_status(status);
                     } else {
                         println!("no inferior running");    // if you type continue before you type run
                     }
                 }
+                DebuggerCommand::Step => self.step(true),
+                DebuggerCommand::Next => self.step(false),
+                DebuggerCommand::List(location) => {
+                    if let Err(err) = self.list(location.as_ref().map(|l| l.as_str())) {
+                        println!("{}", err);
+                    }
+                }
+                DebuggerCommand::Finish => self.finish(),
                 DebuggerCommand::Backtrace => {
-                    if let Some(inf) = self.inferior.as_mut() {
-                        inf.print_backtrace(&self.debug_data).unwrap();
+                    if self.inferior.is_some() {
+                        self.print_backtrace();
                     } else {
                         println!("no inferior running");
                     }
                 }
-                DebuggerCommand::Break(brkp) => {
-                    let b;
-                    if brkp.starts_with("*") {
-                        if let Some(addr) = parse_address(&brkp[1..]) {
-                            b = addr;
-                        } else {
-                            println!("invalid address");
-                            continue;
+                DebuggerCommand::Frame(level) => {
+                    let level = level.unwrap_or(self.selected_frame);
+                    if level > 0 && level >= self.frames.len() {
+                        println!("No frame at level {}.", level);
+                    } else {
+                        self.select_frame(level as isize);
+                    }
+                }
+                DebuggerCommand::Up(count) => {
+                    if self.inferior.is_some() && self.selected_frame + 1 >= self.frames.len() {
+                        println!("Initial frame selected; you cannot go up.");
+                    } else {
+                        self.select_frame((self.selected_frame + count) as isize);
+                    }
+                }
+                DebuggerCommand::Down(count) => {
+                    if self.inferior.is_some() && self.selected_frame == 0 {
+                        println!("Bottom (innermost) frame selected; you cannot go down.");
+                    } else {
+                        self.select_frame(self.selected_frame as isize - count as isize);
+                    }
+                }
+                DebuggerCommand::Print(expression) => {
+                    self.print_expression(&expression);
+                }
+                DebuggerCommand::Break(location, condition) => {
+                    self.add_breakpoint(&location, condition, false);
+                }
+                DebuggerCommand::Tbreak(location, condition) => {
+                    self.add_breakpoint(&location, condition, true);
+                }
+                DebuggerCommand::Until(location) => self.advance(&location, true),
+                DebuggerCommand::Advance(location) => self.advance(&location, false),
+                DebuggerCommand::Condition(id, condition) => {
+                    let parsed = condition.as_ref().map(|cond| self.parse_expression(cond));
+                    if let Some(Err(err)) = parsed {
+                        println!("Invalid condition: {}", err);
+                        continue;
+                    }
+                    match self.breakpoints.get_mut(id) {
+                        Some(bp) => {
+                            if condition.is_none() {
+                                println!("Breakpoint {} now unconditional.", id);
+                            }
+                            bp.condition = condition;
                         }
-                    } else if let Ok(line_num) = usize::from_str_radix(&brkp, 10) {
-                        dbg!(line_num);
-                        if let Some(addr) = self.debug_data.get_addr_for_line(None, line_num) {
-                            b = addr;
-                        } else {
-                            println!("invalid line number");
-                            continue;
+                        None => println!("No breakpoint number {}.", id),
+                    }
+                }
+                DebuggerCommand::Ignore(id, count) => match self.breakpoints.get_mut(id) {
+                    Some(bp) => {
+                        bp.ignore_count = count;
+                        println!("Will ignore next {} crossings of breakpoint {}.", count, id);
+                    }
+                    None => println!("No breakpoint number {}.", id),
+                },
+                DebuggerCommand::Watch(expr, kind) => self.set_watchpoint(&expr, kind),
+                DebuggerCommand::Disassemble(location) => {
+                    if let Err(err) = self.disassemble(location.as_ref().map(|l| l.as_str())) {
+                        println!("{}", err);
+                    }
+                }
+                DebuggerCommand::Examine(spec, address) => {
+                    if self.inferior.is_some() {
+                        if let Err(err) = self.examine(spec, &address) {
+                            println!("{}", err);
                         }
-                    } else if let Some(addr) = self.debug_data.get_addr_for_function(None, &brkp) {
-                        b = addr;
                     } else {
-                        println!("Usage: b|break|breakpoint *address|line|func");
-                        continue;
+                        println!("The program is not being run.");
+                    }
+                }
+                DebuggerCommand::InfoBreakpoints => self.print_breakpoints(),
+                DebuggerCommand::InfoArgs => self.print_frame_variables(true),
+                DebuggerCommand::InfoLocals => self.print_frame_variables(false),
+                DebuggerCommand::InfoThreads => self.print_threads(),
+                DebuggerCommand::InfoSignals(signal) => self.print_signals(signal),
+                DebuggerCommand::Handle(signal, actions) => self.handle_signal(signal, &actions),
+                DebuggerCommand::Thread(id) => self.select_thread(id),
+                DebuggerCommand::InfoFunctions(regex) => {
+                    self.print_symbols(regex.as_ref().map(|r| r.as_str()), true)
+                }
+                DebuggerCommand::InfoVariables(regex) => {
+                    self.print_symbols(regex.as_ref().map(|r| r.as_str()), false)
+                }
+                DebuggerCommand::InfoRegisters(name) => {
+                    if self.inferior.is_some() {
+                        self.print_registers(name.as_ref().map(|name| name.as_str()));
+                    } else {
+                        println!("The program has no registers now.");
+                    }
+                }
+                DebuggerCommand::Set(assignment) => {
+                    if self.inferior.is_some() {
+                        if let Err(err) = self.assign(&assignment) {
+                            println!("{}", err);
+                        }
+                    } else {
+                        println!("The program is not being run.");
+                    }
+                }
+                DebuggerCommand::SetFollowForkMode(mode) => self.follow_fork_mode = mode,
+                DebuggerCommand::SetDetachOnFork(detach) => self.detach_on_fork = detach,
+                DebuggerCommand::Delete(ids) => {
+                    for id in self.breakpoint_ids_or_all(ids) {
+                        self.delete_breakpoint(id);
+                    }
+                }
+                DebuggerCommand::Disable(ids) => {
+                    for id in self.breakpoint_ids_or_all(ids) {
+                        self.set_breakpoint_enabled(id, false);
+                    }
+                }
+                DebuggerCommand::Enable(ids) => {
+                    for id in self.breakpoint_ids_or_all(ids) {
+                        self.set_breakpoint_enabled(id, true);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Attaches to a running process, inserting our breakpoints and watchpoints into it.
+    pub fn attach(&mut self, pid: Pid) {
+        println!("Attaching to process {}", pid);
+        match Inferior::attach(pid) {
+            Ok(inferior) => {
+                self.inferior = Some(inferior);
+                self.modules = unwind::ModuleCache::new();
+            }
+            Err(err) => {
+                println!("Cannot attach to process {}: {}", pid, err);
+                return;
+            }
+        }
+        self.breakpoints.reset_hit_counts();
+        if let Err(err) = self.insert_breakpoints() {
+            println!("Cannot insert breakpoints: {}", err);
+        }
+        self.update_debug_registers();
+        let inf = self.inferior.as_ref().unwrap();
+        match inf.get_registers() {
+            Ok(regs) => {
+                let rip = regs.rip as usize;
+                match self.debug_data.get_line_from_addr(rip) {
+                    Some(line) => println!("Stopped at {}", line),
+                    None => println!("Stopped at {:#x}", rip),
+                }
+            }
+            Err(err) => println!("Error reading registers: {}", err),
+        }
+        self.refresh_frames();
+    }
+
+    /// Loads a core file, to examine the process that dumped it as it was when it died.
+    pub fn load_core(&mut self, path: &str) {
+        match Inferior::from_core(path) {
+            Ok(inferior) => {
+                self.inferior = Some(inferior);
+                self.modules = unwind::ModuleCache::new();
+            }
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        }
+        let core = self.inferior.as_ref().unwrap().core().unwrap();
+        println!("Core was generated by `{}'.", core.command);
+        if let Some(signal) = core.signal {
+            println!(
+                "Program terminated with signal {}, {}.",
+                signal,
+                signals::describe(signal)
+            );
+            self.print_fault_address(signal);
+        }
+        self.refresh_frames();
+        self.select_frame(0);
+    }
+
+    /// Whether the inferior is a core file rather than a live process.
+    fn examining_core(&self) -> bool {
+        self.inferior
+            .as_ref()
+            .map_or(false, |inf| inf.core().is_some())
+    }
+
+    /// Returns the inferior if it is a live process, which breakpoints and watchpoints can be
+    /// inserted into. For a core file, they are only recorded, as before "run".
+    fn process_mut(&mut self) -> Option<&mut Inferior> {
+        self.inferior.as_mut().filter(|inf| inf.core().is_none())
+    }
+
+    /// Restores every byte we patched and lets the inferior continue without us.
+    fn detach(&mut self) {
+        let inf = self.inferior.take().unwrap();
+        let pid = inf.pid();
+        match inf.detach(&self.breakpoints.inserted()) {
+            Ok(()) => println!("Detaching from process {}", pid),
+            Err(err) => println!("Error detaching from process {}: {}", pid, err),
+        }
+        self.release_held();
+        self.refresh_frames();
+    }
+
+    /// Gets rid of the processes held after forks, detaching from them if they descend from a
+    /// process we attached to, and killing them otherwise.
+    fn release_held(&mut self) {
+        let inserted = self.breakpoints.inserted();
+        for mut inf in self.held.drain(..) {
+            if !inf.is_attached() {
+                inf.kill();
+                continue;
+            }
+            let pid = inf.pid();
+            if let Err(err) = inf.detach(&inserted) {
+                println!("Error detaching from process {}: {}", pid, err);
+            }
+        }
+    }
+
+    /// Makes the oldest held process the inferior, after the inferior is gone.
+    fn switch_to_held(&mut self) {
+        if self.held.is_empty() {
+            return;
+        }
+        let inf = self.held.remove(0);
+        println!("[Switching to process {}]", inf.pid());
+        self.inferior = Some(inf);
+        self.modules = unwind::ModuleCache::new();
+        self.update_debug_registers();
+    }
+
+    /// Called when the inferior forks, with the breakpoints (including temporary ones) that the
+    /// new process inherited. Carries on debugging the parent or the child according to
+    /// follow-fork-mode, and detaches from the other (once its breakpoints are removed) or holds
+    /// it according to detach-on-fork.
+    fn follow_fork(
+        &mut self,
+        child_pid: Pid,
+        inserted: &HashMap<usize, u8>,
+    ) -> Result<(), nix::Error> {
+        let parent = self.inferior.take().unwrap();
+        let child = Inferior::from_fork(child_pid, &parent)?;
+        let (followed, other) = match self.follow_fork_mode {
+            FollowForkMode::Parent => (parent, child),
+            FollowForkMode::Child => {
+                println!(
+                    "[Attaching after process {} fork to child process {}]",
+                    parent.pid(),
+                    child_pid
+                );
+                (child, parent)
+            }
+        };
+        self.inferior = Some(followed);
+        // Watchpoints aren't inherited by forked processes
+        self.update_debug_registers();
+        let other_pid = other.pid();
+        if self.detach_on_fork {
+            if self.follow_fork_mode == FollowForkMode::Parent {
+                println!("[Detaching after fork from child process {}]", other_pid);
+            } else {
+                println!("[Detaching after fork from parent process {}]", other_pid);
+            }
+            other.detach(inserted)?;
+        } else {
+            // Only real breakpoints stay inserted in a held process
+            let mut other = other;
+            let breakpoints = self.breakpoints.inserted();
+            for (addr, orig_byte) in inserted {
+                if !breakpoints.contains_key(addr) {
+                    other.write_byte(*addr, *orig_byte)?;
+                }
+            }
+            println!("[Process {} will be held stopped]", other_pid);
+            self.held.push(other);
+        }
+        Ok(())
+    }
+
+    /// Called when the inferior calls exec: loads the new program's debugging information,
+    /// and moves breakpoints to where their locations are in it. Watchpoints are deleted, since
+    /// what they watched is gone.
+    fn follow_exec(&mut self) {
+        let pid = self.inferior.as_ref().unwrap().pid();
+        let path = match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => self.target.clone(),
+        };
+        println!("process {} is executing new program: {}", pid, path);
+        match DwarfData::from_file(&path) {
+            Ok(debug_data) => {
+                self.debug_data = debug_data;
+                self.target = path;
+            }
+            Err(_) => println!("Could not load debugging symbols from {}", path),
+        }
+        self.list_position = None;
+        self.frames.clear();
+        self.modules = unwind::ModuleCache::new();
+        let watchpoints: Vec<usize> = self.breakpoints.watchpoints().map(|wp| wp.id).collect();
+        for id in watchpoints {
+            self.breakpoints.remove_watchpoint(id);
+            println!("Watchpoint {} deleted because the program has changed.", id);
+        }
+        for id in self.breakpoints.ids() {
+            let location = self.breakpoints.get(id).unwrap().location.clone();
+            match location.map(|location| self.resolve_breakpoint_location(&location)) {
+                Some(Ok(addr)) => self.breakpoints.get_mut(id).unwrap().addr = addr,
+                _ => {
+                    println!(
+                        "Breakpoint {} disabled: its location is not in the new program.",
+                        id
+                    );
+                    self.breakpoints.get_mut(id).unwrap().enabled = false;
+                }
+            }
+        }
+        if let Err(err) = self.insert_breakpoints() {
+            println!("Cannot insert breakpoints: {}", err);
+        }
+        self.update_debug_registers();
+    }
+
+    /// Implements "break" and "tbreak".
+    fn add_breakpoint(&mut self, location: &str, condition: Option<String>, temporary: bool) {
+        let parsed = condition.as_ref().map(|cond| self.parse_expression(cond));
+        if let Some(Err(err)) = parsed {
+            println!("Invalid condition: {}", err);
+            return;
+        }
+        let addr = match self.resolve_breakpoint_location(location) {
+            Ok(addr) => addr,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        if let Some(id) = self.set_breakpoint(addr, temporary) {
+            let bp = self.breakpoints.get_mut(id).unwrap();
+            bp.condition = condition;
+            bp.location = Some(location.to_string());
+        }
+    }
+
+    /// Sets a breakpoint at `addr`, returning its id.
+    fn set_breakpoint(&mut self, addr: usize, temporary: bool) -> Option<usize> {
+        // Before the inferior starts, breakpoints are only recorded; they get inserted on "run"
+        let orig_byte = match self.breakpoints.inserted_at(addr) {
+            Some(orig_byte) => orig_byte,
+            None => match self.process_mut() {
+                Some(inf) => match inf.write_byte(addr, 0xcc) {
+                    Ok(orig_byte) => orig_byte,
+                    Err(err) => {
+                        println!("Cannot insert breakpoint at {:#x}: {}", addr, err);
+                        return None;
+                    }
+                },
+                None => 0,
+            },
+        };
+        let bp = self.breakpoints.add(addr, orig_byte);
+        bp.temporary = temporary;
+        let id = bp.id;
+        let kind = if temporary {
+            "temporary breakpoint"
+        } else {
+            "breakpoint"
+        };
+        match self.debug_data.get_line_from_addr(addr) {
+            Some(line) => println!("Set {} {} at {:#x}: {}", kind, id, addr, line),
+            None => println!("Set {} {} at {:#x}", kind, id, addr),
+        }
+        Some(id)
+    }
+
+    /// Commands like "delete" apply to every breakpoint when no numbers are given.
+    fn breakpoint_ids_or_all(&self, ids: Vec<usize>) -> Vec<usize> {
+        if ids.is_empty() {
+            self.breakpoints.ids()
+        } else {
+            ids
+        }
+    }
+
+    fn delete_breakpoint(&mut self, id: usize) {
+        if let Some(bp) = self.breakpoints.remove(id) {
+            if bp.enabled {
+                self.restore_orig_byte(bp.addr, bp.orig_byte);
+            }
+        } else if self.breakpoints.remove_watchpoint(id).is_some() {
+            self.update_debug_registers();
+        } else {
+            println!("No breakpoint number {}.", id);
+        }
+    }
+
+    fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) {
+        if self.breakpoints.get_watchpoint_mut(id).is_some() {
+            self.set_watchpoint_enabled(id, enabled);
+            return;
+        }
+        let (addr, orig_byte) = match self.breakpoints.get(id) {
+            Some(bp) if bp.enabled == enabled => return,
+            Some(bp) => (bp.addr, bp.orig_byte),
+            None => {
+                println!("No breakpoint number {}.", id);
+                return;
+            }
+        };
+        if enabled {
+            let orig_byte = match (self.breakpoints.inserted_at(addr), self.process_mut()) {
+                (Some(orig_byte), _) => orig_byte,
+                (None, Some(inf)) => match inf.write_byte(addr, 0xcc) {
+                    Ok(orig_byte) => orig_byte,
+                    Err(err) => {
+                        println!("Cannot insert breakpoint {}: {}", id, err);
+                        return;
+                    }
+                },
+                (None, None) => 0,
+            };
+            let bp = self.breakpoints.get_mut(id).unwrap();
+            bp.orig_byte = orig_byte;
+            bp.enabled = true;
+        } else {
+            self.breakpoints.get_mut(id).unwrap().enabled = false;
+            self.restore_orig_byte(addr, orig_byte);
+        }
+    }
+
+    /// Puts the original byte back at `addr` in the running inferior, unless another enabled
+    /// breakpoint still needs the 0xcc there.
+    fn restore_orig_byte(&mut self, addr: usize, orig_byte: u8) {
+        if self.breakpoints.inserted_at(addr).is_some() {
+            return;
+        }
+        if let Some(inf) = self.process_mut() {
+            if let Err(err) = inf.write_byte(addr, orig_byte) {
+                println!("Cannot remove breakpoint at {:#x}: {}", addr, err);
+            }
+        }
+    }
+
+    /// Writes 0xcc at the address of every enabled breakpoint in a freshly started inferior.
+    fn insert_breakpoints(&mut self) -> Result<(), nix::Error> {
+        let inf = self.inferior.as_mut().unwrap();
+        let mut inserted: HashMap<usize, u8> = HashMap::new();
+        for bp in self.breakpoints.iter_mut().filter(|bp| bp.enabled) {
+            bp.orig_byte = match inserted.get(&bp.addr) {
+                Some(orig_byte) => *orig_byte,
+                None => {
+                    let orig_byte = inf.write_byte(bp.addr, 0xcc)?;
+                    inserted.insert(bp.addr, orig_byte);
+                    orig_byte
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.ids().is_empty() {
+            println!("No breakpoints.");
+            return;
+        }
+        println!(
+            "{:<8}{:<5}{:<5}{:<20}{:<6}{}",
+            "Num", "Disp", "Enb", "Address", "Hits", "What"
+        );
+        for bp in self.breakpoints.iter() {
+            let what = match self.debug_data.get_line_from_addr(bp.addr) {
+                Some(line) => match self.debug_data.get_function_from_addr(bp.addr) {
+                    Some(func) => format!("in {} at {}", func, line),
+                    None => format!("at {}", line),
+                },
+                None => String::new(),
+            };
+            println!(
+                "{:<8}{:<5}{:<5}{:<20}{:<6}{}",
+                bp.id,
+                if bp.temporary { "del" } else { "keep" },
+                if bp.enabled { "y" } else { "n" },
+                format!("{:#x}", bp.addr),
+                bp.hit_count,
+                what
+            );
+            if let Some(condition) = &bp.condition {
+                println!("\tstop only if {}", condition);
+            }
+            if bp.ignore_count > 0 {
+                println!(
+                    "\tWill ignore next {} crossings of breakpoint.",
+                    bp.ignore_count
+                );
+            }
+        }
+        for wp in self.breakpoints.watchpoints() {
+            let kind = match wp.kind {
+                WatchKind::Write => "hw watchpoint",
+                WatchKind::Read => "read watchpoint",
+                WatchKind::Access => "acc watchpoint",
+            };
+            println!(
+                "{:<8}{:<5}{:<5}{:<20}{:<6}{}: {}",
+                wp.id,
+                "keep",
+                if wp.enabled { "y" } else { "n" },
+                format!("{:#x}", wp.addr),
+                wp.hit_count,
+                kind,
+                wp.expr
+            );
+        }
+    }
+
+    /// Sets a hardware watchpoint on a variable, or on the word at `*address`.
+    fn set_watchpoint(&mut self, expr: &str, kind: WatchKind) {
+        if self.breakpoints.enabled_watchpoint_count() >= MAX_WATCHPOINTS {
+            println!(
+                "Too many hardware watchpoints (at most {}).",
+                MAX_WATCHPOINTS
+            );
+            return;
+        }
+        let (addr, len, entity_type, scope) = if expr.starts_with('*') {
+            match parse_address(&expr[1..]) {
+                Some(addr) => (addr, size_of::<usize>(), None, None),
+                None => {
+                    println!("invalid address");
+                    return;
+                }
+            }
+        } else {
+            // Locals can only be found in a running inferior, but globals have a fixed address
+            let var = match self
+                .inferior
+                .as_ref()
+                .map(|inf| (inf, self.selected_frame()))
+            {
+                Some((inf, Ok(frame))) => {
+                    match self.debug_data.find_variable(expr, frame.lookup_pc()) {
+                        Some(var) => {
+                            match location::variable_address(&self.debug_data, inf, var, frame) {
+                                Ok(addr) => Some((addr, var, self.watch_scope(var))),
+                                Err(err) => {
+                                    println!("{}", err);
+                                    return;
+                                }
+                            }
+                        }
+                        None => None,
+                    }
+                }
+                Some((_, Err(err))) => {
+                    println!("{}", err);
+                    return;
+                }
+                None => {
+                    self.debug_data
+                        .get_global_variable(expr)
+                        .and_then(|var| match var.location {
+                            Location::Address(addr) => Some((addr, var, None)),
+                            _ => None,
+                        })
+                }
+            };
+            match var {
+                Some((addr, var, scope)) => (
+                    addr,
+                    var.entity_type.size,
+                    Some(var.entity_type.clone()),
+                    scope,
+                ),
+                None => {
+                    println!("No symbol \"{}\" in current context.", expr);
+                    return;
+                }
+            }
+        };
+        if ![1, 2, 4, 8].contains(&len) || addr % len != 0 {
+            println!(
+                "Cannot watch {}: the hardware can only watch aligned 1, 2, 4 or 8 byte regions",
+                expr
+            );
+            return;
+        }
+        let wp =
+            self.breakpoints
+                .add_watchpoint(expr.to_string(), addr, len, kind, entity_type, scope);
+        let description = match kind {
+            WatchKind::Write => "Hardware watchpoint",
+            WatchKind::Read => "Hardware read watchpoint",
+            WatchKind::Access => "Hardware access (read/write) watchpoint",
+        };
+        println!("{} {}: {}", description, wp.id, expr);
+        self.update_debug_registers();
+    }
+
+    /// Works out which frame a watched variable lives in, if it is a local (as opposed to a
+    /// global or static, which live as long as the program). Locals of main are left alone, since
+    /// main's frame lasts until the program exits.
+    fn watch_scope(&self, var: &Variable) -> Option<WatchScope> {
+        if let Location::Address(_) = var.location {
+            return None;
+        }
+        let frame = self.frames.get(self.selected_frame)?;
+        let caller = self.frames.get(self.selected_frame + 1)?;
+        Some(WatchScope {
+            tid: self.inferior.as_ref()?.current_thread().tid,
+            cfa: frame.cfa?,
+            return_addr: caller.pc(),
+        })
+    }
+
+    /// Deletes the watchpoints on locals whose frames have returned. Returns whether there were
+    /// any, which stops the inferior.
+    fn delete_out_of_scope_watchpoints(&mut self) -> bool {
+        let inf = self.inferior.as_ref().unwrap();
+        let gone: Vec<usize> = self
+            .breakpoints
+            .watchpoints()
+            .filter(|wp| match &wp.scope {
+                // A thread that has exited has no frames left
+                Some(scope) => inf
+                    .get_thread_registers(scope.tid)
+                    .map_or(true, |regs| regs.rsp as usize >= scope.cfa),
+                None => false,
+            })
+            .map(|wp| wp.id)
+            .collect();
+        for id in &gone {
+            self.breakpoints.remove_watchpoint(*id);
+            println!();
+            println!(
+                "Watchpoint {} deleted because the program has left the block in which its \
+                 expression is valid.",
+                id
+            );
+        }
+        if !gone.is_empty() {
+            self.watchpoint_hit = true;
+            self.update_debug_registers();
+        }
+        !gone.is_empty()
+    }
+
+    fn set_watchpoint_enabled(&mut self, id: usize, enabled: bool) {
+        let count = self.breakpoints.enabled_watchpoint_count();
+        let wp = self.breakpoints.get_watchpoint_mut(id).unwrap();
+        if wp.enabled == enabled {
+            return;
+        }
+        if enabled && count >= MAX_WATCHPOINTS {
+            println!(
+                "Too many hardware watchpoints (at most {}).",
+                MAX_WATCHPOINTS
+            );
+            return;
+        }
+        wp.enabled = enabled;
+        self.update_debug_registers();
+    }
+
+    /// Points DR0-DR3 at the enabled watchpoints and enables them in DR7. Also records the
+    /// current value of each watched region, to compare against when a watchpoint triggers.
+    fn update_debug_registers(&mut self) {
+        if self.examining_core() {
+            return;
+        }
+        let inf = match self.inferior.as_mut() {
+            Some(inf) => inf,
+            None => return,
+        };
+        let mut dr7 = 0;
+        for (slot, wp) in self.breakpoints.enabled_watchpoints_mut().enumerate() {
+            if let Err(err) = inf.set_debug_register(slot, wp.addr as u64) {
+                println!("Cannot set watchpoint {}: {}", wp.id, err);
+                continue;
+            }
+            wp.old_value = inf.read_bytes(wp.addr, wp.len).unwrap_or_default();
+            // x86 can't trap on reads alone, so read watchpoints trap on any access and we
+            // check afterwards whether the value changed
+            let rw = match wp.kind {
+                WatchKind::Write => 0b01,
+                WatchKind::Read | WatchKind::Access => 0b11,
+            };
+            let len = match wp.len {
+                1 => 0b00,
+                2 => 0b01,
+                8 => 0b10,
+                _ => 0b11,
+            };
+            dr7 |= 1 << (slot * 2);
+            dr7 |= (rw | len << 2) << (16 + slot * 4);
+        }
+        if let Err(err) = inf.set_debug_register(7, dr7) {
+            println!("Cannot set watchpoints: {}", err);
+        }
+    }
+
+    /// Called when the inferior gets a SIGTRAP. Returns None if no watchpoint caused it;
+    /// otherwise reports the watchpoints that triggered and returns whether to stop.
+    fn check_watchpoints(&mut self) -> Result<Option<bool>, nix::Error> {
+        if self.breakpoints.enabled_watchpoint_count() == 0 {
+            return Ok(None);
+        }
+        let debug_data = &self.debug_data;
+        let inf = self.inferior.as_mut().unwrap();
+        // The low four bits of DR6 say which of DR0-DR3 triggered
+        let dr6 = inf.get_debug_register(6)?;
+        if dr6 & 0xf == 0 {
+            return Ok(None);
+        }
+        inf.set_debug_register(6, 0)?;
+        let mut stop = false;
+        for (slot, wp) in self.breakpoints.enabled_watchpoints_mut().enumerate() {
+            if dr6 & (1 << slot) == 0 {
+                continue;
+            }
+            let new_value = inf.read_bytes(wp.addr, wp.len)?;
+            let changed = new_value != wp.old_value;
+            let triggered = match wp.kind {
+                WatchKind::Write => changed,
+                WatchKind::Read => !changed,
+                WatchKind::Access => true,
+            };
+            if triggered {
+                wp.hit_count += 1;
+                stop = true;
+                self.watchpoint_hit = true;
+                let format = |bytes: &[u8]| match &wp.entity_type {
+                    Some(ty) => value::format_value(debug_data, inf, ty, bytes),
+                    None => format!("{:#x}", value::bytes_to_u64(bytes)),
+                };
+                match wp.kind {
+                    WatchKind::Write => println!("Hardware watchpoint {}: {}", wp.id, wp.expr),
+                    WatchKind::Read => println!("Hardware read watchpoint {}: {}", wp.id, wp.expr),
+                    WatchKind::Access => {
+                        println!(
+                            "Hardware access (read/write) watchpoint {}: {}",
+                            wp.id, wp.expr
+                        )
+                    }
+                }
+                println!();
+                if changed {
+                    println!("Old value = {}", format(&wp.old_value));
+                    println!("New value = {}", format(&new_value));
+                } else {
+                    println!("Value = {}", format(&new_value));
+                }
+            }
+            wp.old_value = new_value;
+        }
+        Ok(Some(stop))
+    }
+
+    /// Resumes the inferior until it stops at a breakpoint, gets a signal or exits. It also stops
+    /// when it gets to any of `temp_addrs`. Breakpoints whose
+    /// condition is false, or which are being ignored, don't stop the inferior.
+    fn resume(&mut self, mut temp_addrs: &[usize]) -> Result<inferior::Status, nix::Error> {
+        let inf = self.inferior.as_mut().unwrap();
+        let mut inserted = self.breakpoints.inserted();
+        let mut temp = Vec::new();
+        // Stop where the frames of watched locals return to, to see if they have
+        let scope_addrs: Vec<usize> = self
+            .breakpoints
+            .watchpoints()
+            .filter_map(|wp| wp.scope.as_ref().map(|scope| scope.return_addr))
+            .collect();
+        for &addr in temp_addrs.iter().chain(&scope_addrs) {
+            if !inserted.contains_key(&addr) {
+                let orig_byte = inf.write_byte(addr, 0xcc)?;
+                inserted.insert(addr, orig_byte);
+                temp.push((addr, orig_byte));
+            }
+        }
+        self.watchpoint_hit = false;
+        self.breakpoints_hit.clear();
+        if self.breakpoints.enabled_watchpoint_count() > 0 {
+            inf.set_debug_register(6, 0)?;
+        }
+        let status = loop {
+            let status = self
+                .inferior
+                .as_mut()
+                .unwrap()
+                .cont(&inserted, &self.signals)?;
+            match status {
+                inferior::Status::Stopped(Signal::SIGTRAP, rip) => {
+                    if let Some(stop) = self.check_watchpoints()? {
+                        if stop {
+                            break status;
+                        }
+                    } else if !inserted.contains_key(&rip) {
+                        // Not one of ours (e.g. an int3 compiled into the program)
+                        break status;
+                    } else {
+                        // Breakpoints here count their hits even if a temporary stop is here too
+                        self.breakpoints_hit = self.should_stop_at_breakpoint(rip);
+                        let out_of_scope =
+                            scope_addrs.contains(&rip) && self.delete_out_of_scope_watchpoints();
+                        let at_breakpoint = !self.breakpoints_hit.is_empty();
+                        if at_breakpoint || out_of_scope || temp_addrs.contains(&rip) {
+                            break status;
+                        }
+                    }
+                }
+                inferior::Status::Forked(child) => self.follow_fork(child, &inserted)?,
+                inferior::Status::Execed => {
+                    self.follow_exec();
+                    // The temporary stops were addresses in the old program
+                    inserted = self.breakpoints.inserted();
+                    temp.clear();
+                    temp_addrs = &[];
+                }
+                _ => break status,
+            }
+        };
+        if let inferior::Status::Stopped(_, _) = status {
+            let inf = self.inferior.as_mut().unwrap();
+            for (addr, orig_byte) in temp {
+                inf.write_byte(addr, orig_byte)?;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Called when the inferior traps at `addr`. Counts a hit for each breakpoint there whose
+    /// condition holds, and returns the ids of those that should stop the inferior (the ones not
+    /// being ignored).
+    fn should_stop_at_breakpoint(&mut self, addr: usize) -> Vec<usize> {
+        let here: Vec<(usize, Option<String>)> = self
+            .breakpoints
+            .iter()
+            .filter(|bp| bp.enabled && bp.addr == addr)
+            .map(|bp| (bp.id, bp.condition.clone()))
+            .collect();
+        let mut stop = Vec::new();
+        for (id, condition) in here {
+            let triggered = match condition {
+                Some(condition) => match self.evaluate_condition(&condition) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        println!("Error in testing condition for breakpoint {}: {}", id, err);
+                        true
+                    }
+                },
+                None => true,
+            };
+            if triggered {
+                let bp = self.breakpoints.get_mut(id).unwrap();
+                bp.hit_count += 1;
+                if bp.ignore_count > 0 {
+                    bp.ignore_count -= 1;
+                } else {
+                    stop.push(id);
+                }
+            }
+        }
+        stop
+    }
+
+    fn evaluate_condition(&mut self, condition: &str) -> Result<bool, String> {
+        // The inferior is stopped at a breakpoint, which is always in the innermost frame
+        let inf = self.inferior.as_ref().unwrap();
+        let frame = unwind::innermost_frame(inf, &mut self.modules);
+        let frame = frame.map_err(|err| err.to_string())?;
+        let expr = self.parse_expression(condition)?;
+        let evaluator = Evaluator::new(&self.debug_data, inf, &frame);
+        evaluator.is_true(&evaluator.evaluate(&expr)?)
+    }
+
+    /// Parses an expression. Typedef names have to be recognized so that casts can be.
+    fn parse_expression(&self, expression: &str) -> Result<Expr, String> {
+        expr::parse(expression, &|name| {
+            self.debug_data.get_type_by_name(name).is_some()
+        })
+    }
+
+    /// Evaluates an expression in the selected frame, returning it as an integer (or address).
+    fn evaluate_integer(&self, expression: &str) -> Result<i64, String> {
+        let frame = self.selected_frame()?;
+        let evaluator = Evaluator::new(&self.debug_data, self.inferior.as_ref().unwrap(), frame);
+        evaluator.to_integer(&evaluator.evaluate(&self.parse_expression(expression)?)?)
+    }
+
+    /// Prints registers the way gdb's `info registers` does: name, hex value, then the value in
+    /// decimal, or decoded for rip and eflags.
+    fn print_registers(&self, name: Option<&str>) {
+        let regs = match self.inferior.as_ref().unwrap().get_registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Error reading registers: {}", err);
+                return;
+            }
+        };
+        let names = match name {
+            Some(name) => {
+                let name = if name.starts_with('$') {
+                    &name[1..]
+                } else {
+                    name
+                };
+                if inferior::get_register(&regs, name).is_none() {
+                    println!("Invalid register `{}'", name);
+                    return;
+                }
+                vec![name]
+            }
+            None => inferior::REGISTER_NAMES.to_vec(),
+        };
+        for name in names {
+            let value = inferior::get_register(&regs, name).unwrap();
+            let detail = match name {
+                "rip" | "pc" => match self.debug_data.get_function_containing(value as usize) {
+                    Some(func) => format!("<{}+{}>", func.name, value as usize - func.address),
+                    None => String::new(),
+                },
+                "eflags" => inferior::format_eflags(value),
+                "rsp" | "rbp" | "fs_base" | "gs_base" => format!("{:#x}", value),
+                _ => (value as i64).to_string(),
+            };
+            println!("{:<15}{:<19}{}", name, format!("{:#x}", value), detail);
+        }
+    }
+
+    /// Carries out an assignment like `$rax = 5`.
+    fn assign(&mut self, assignment: &str) -> Result<(), String> {
+        let index = assignment_operator(assignment)
+            .ok_or_else(|| "Expected an assignment such as \"var x = 5\"".to_string())?;
+        let lhs = self.parse_expression(&assignment[..index])?;
+        let rhs = self.parse_expression(&assignment[index + 1..])?;
+        if let Expr::Register(name) = &lhs {
+            let value = self.evaluate_integer(&assignment[index + 1..])?;
+            let inf = self.inferior.as_ref().unwrap();
+            let mut regs = inf.get_registers().map_err(|err| err.to_string())?;
+            let reg = inferior::register_mut(&mut regs, name)
+                .ok_or_else(|| format!("Invalid register \"${}\"", name))?;
+            *reg = value as u64;
+            inf.set_registers(regs).map_err(|err| err.to_string())?;
+            self.refresh_frames();
+            return Ok(());
+        }
+
+        let (addr, mut bytes) = {
+            let frame = self.selected_frame()?;
+            let evaluator =
+                Evaluator::new(&self.debug_data, self.inferior.as_ref().unwrap(), frame);
+            let target = evaluator.evaluate(&lhs)?;
+            let addr = target
+                .address
+                .ok_or_else(|| "Left operand of assignment is not an lvalue.".to_string())?;
+            let value = evaluator.convert_for_assignment(evaluator.evaluate(&rhs)?, &target)?;
+            (addr, value.bytes)
+        };
+        // Keep any breakpoints in the way inserted, remembering the new byte to restore instead
+        for bp in self.breakpoints.iter_mut() {
+            if bp.enabled && bp.addr >= addr && bp.addr < addr + bytes.len() {
+                bp.orig_byte = bytes[bp.addr - addr];
+                bytes[bp.addr - addr] = 0xcc;
+            }
+        }
+        self.inferior
+            .as_mut()
+            .unwrap()
+            .write_bytes(addr, &bytes)
+            .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+        // The write may have changed a watched value (or a saved return address)
+        self.update_debug_registers();
+        self.refresh_frames();
+        Ok(())
+    }
+
+    /// Resumes the inferior until the frame whose canonical frame address is `cfa` returns to
+    /// `return_addr`. Returns from deeper (recursive) frames to the same address are skipped.
+    fn run_to_return(
+        &mut self,
+        return_addr: usize,
+        cfa: usize,
+    ) -> Result<inferior::Status, nix::Error> {
+        loop {
+            let status = self.resume(&[return_addr])?;
+            let rsp = self.inferior.as_ref().unwrap().get_registers()?.rsp as usize;
+            match status {
+                inferior::Status::Stopped(Signal::SIGTRAP, rip)
+                    if rip == return_addr && rsp < cfa => {}
+                _ => return Ok(status),
+            }
+        }
+    }
+
+    /// Unwinds the stack after the inferior stops (or goes away), selecting the innermost frame.
+    fn refresh_frames(&mut self) {
+        self.list_position = None;
+        self.selected_frame = 0;
+        let modules = &mut self.modules;
+        self.frames = match self
+            .inferior
+            .as_ref()
+            .map(|inf| unwind::backtrace(inf, modules))
+        {
+            Some(Ok(frames)) => frames,
+            _ => Vec::new(),
+        };
+        // Like backtraces, frame selection stops at main
+        let debug_data = &self.debug_data;
+        let main = self.frames.iter().position(|frame| {
+            debug_data
+                .get_function_from_addr(frame.lookup_pc())
+                .as_deref()
+                == Some("main")
+        });
+        if let Some(main) = main {
+            self.frames.truncate(main + 1);
+        }
+    }
+
+    /// Returns the selected frame, which is the innermost one unless `frame`, `up` or `down` was
+    /// used since the inferior last stopped.
+    fn selected_frame(&self) -> Result<&Frame, String> {
+        self.frames
+            .get(self.selected_frame)
+            .ok_or_else(|| "No stack.".to_string())
+    }
+
+    /// Describes a frame like "#1  middle at deep.c:7".
+    fn describe_frame(&self, frame: &Frame) -> String {
+        let pc = frame.lookup_pc();
+        let func = self.debug_data.get_function_from_addr(pc);
+        let func = func.as_deref().unwrap_or("??");
+        match self.debug_data.get_line_from_addr(pc) {
+            Some(line) => format!("#{:<3}{} at {}", frame.level, func, line),
+            None => format!("#{:<3}{:#x} in {}", frame.level, frame.pc(), func),
+        }
+    }
+
+    fn print_backtrace(&self) {
+        for frame in &self.frames {
+            println!("{}", self.describe_frame(frame));
+        }
+    }
+
+    /// Lists the inferior's threads like gdb's `info threads`, marking the current one with "*".
+    fn print_threads(&self) {
+        let inf = match self.inferior.as_ref() {
+            Some(inf) => inf,
+            None => {
+                println!("No threads.");
+                return;
+            }
+        };
+        let current = inf.current_thread().id;
+        println!("  {:<5}{:<15}{}", "Id", "Target Id", "Frame");
+        for thread in inf.threads() {
+            let marker = if thread.id == current { "*" } else { " " };
+            let frame = match inf.get_thread_registers(thread.tid) {
+                Ok(regs) => {
+                    let pc = regs.rip as usize;
+                    let func = self.debug_data.get_function_from_addr(pc);
+                    let func = func.as_deref().unwrap_or("??");
+                    match self.debug_data.get_line_from_addr(pc) {
+                        Some(line) => format!("{} at {}", func, line),
+                        None => format!("{:#x} in {}", pc, func),
                     }
+                }
+                Err(err) => format!("<error: {}>", err),
+            };
+            let target_id = format!("LWP {}", thread.tid);
+            println!("{} {:<5}{:<15}{}", marker, thread.id, target_id, frame);
+        }
+    }
+
+    /// Implements "handle": applies the actions to a signal's policy, then shows the policy.
+    fn handle_signal(&mut self, signal: Signal, actions: &[SignalAction]) {
+        if signal == Signal::SIGTRAP && !actions.is_empty() {
+            println!("SIGTRAP is used by the debugger for breakpoints and stepping.");
+            return;
+        }
+        self.signals.update(signal, actions);
+        self.print_signals(Some(signal));
+    }
+
+    /// Lists how signals are handled like gdb's `info signals`.
+    fn print_signals(&self, only: Option<Signal>) {
+        let yes_no = |flag| if flag { "Yes" } else { "No" };
+        println!(
+            "{:<11}{:<6}{:<7}{:<17}{}",
+            "Signal", "Stop", "Print", "Pass to program", "Description"
+        );
+        for signal in Signal::iterator().filter(|signal| only.is_none() || only == Some(*signal)) {
+            let policy = self.signals.get(signal);
+            println!(
+                "{:<11}{:<6}{:<7}{:<17}{}",
+                signal.as_str(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass),
+                signals::describe(signal)
+            );
+        }
+    }
+
+    /// For a signal caused by an instruction going wrong, says what address it tried to access,
+    /// and for a segfault, what was wrong with it.
+    fn print_fault_address(&self, signal: Signal) {
+        let is_fault = matches!(
+            signal,
+            Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGILL | Signal::SIGFPE
+        );
+        if !is_fault {
+            return;
+        }
+        let inf = self.inferior.as_ref().unwrap();
+        let addr = match inf.get_fault_address() {
+            Ok(addr) => addr,
+            Err(_) => return,
+        };
+        // A core file doesn't say much about how memory was mapped
+        if signal != Signal::SIGSEGV || inf.core().is_some() {
+            println!("Fault address: {:#x}", addr);
+            return;
+        }
+        let mappings = inf.mappings().unwrap_or_default();
+        println!(
+            "Fault address: {:#x} ({})",
+            addr,
+            describe_bad_address(addr, &mappings)
+        );
+    }
+
+    /// After a segfault, shows the stack and the variables of the innermost frame we have debug
+    /// info for, which is selected so that they can be looked at further. (The innermost frame
+    /// itself may be in a library that the bad pointer was passed to.)
+    fn print_crash_report(&mut self) {
+        println!("Backtrace:");
+        for frame in self.frames.iter().take(CRASH_BACKTRACE_SIZE) {
+            println!("{}", self.describe_frame(frame));
+        }
+        if self.frames.len() > CRASH_BACKTRACE_SIZE {
+            println!(
+                "(and {} more frames)",
+                self.frames.len() - CRASH_BACKTRACE_SIZE
+            );
+        }
+        let debug_data = &self.debug_data;
+        let level = self.frames.iter().position(|frame| {
+            debug_data
+                .get_function_containing(frame.lookup_pc())
+                .is_some()
+        });
+        let level = match level {
+            Some(level) => level,
+            None => return,
+        };
+        self.selected_frame = level;
+        println!(
+            "Variables in frame {}:",
+            self.describe_frame(&self.frames[level])
+        );
+        self.print_frame_variables(true);
+        self.print_frame_variables(false);
+    }
+
+    /// Implements "thread": switches to another thread, or says which one is current.
+    fn select_thread(&mut self, id: Option<usize>) {
+        let inf = match self.inferior.as_mut() {
+            Some(inf) => inf,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        match id {
+            None => {
+                let thread = inf.current_thread();
+                println!("[Current thread is {} (LWP {})]", thread.id, thread.tid);
+            }
+            Some(id) if inf.select_thread(id) => {
+                println!(
+                    "[Switching to thread {} (LWP {})]",
+                    id,
+                    inf.current_thread().tid
+                );
+                self.refresh_frames();
+                self.select_frame(0);
+            }
+            Some(id) => println!("Invalid thread ID: {}", id),
+        }
+    }
 
-                    println!("Set breakpoint {} at {}", self.breakpoints.len(), b);
+    /// Selects a frame and shows where it is.
+    fn select_frame(&mut self, level: isize) {
+        if self.frames.is_empty() {
+            println!("No stack.");
+            return;
+        }
+        // Like gdb, going too far up or down stops at the outermost or innermost frame
+        let level = level.max(0).min(self.frames.len() as isize - 1) as usize;
+        self.selected_frame = level;
+        self.list_position = None;
+        let frame = self.frames[level];
+        println!("{}", self.describe_frame(&frame));
+        if let Some(line) = self.debug_data.get_line_from_addr(frame.lookup_pc()) {
+            if let Ok(source) = std::fs::read_to_string(&line.file) {
+                if let Some(text) = source.lines().nth(line.number - 1) {
+                    println!("{}\t{}", line.number, text);
+                }
+            }
+        }
+    }
 
-                    if let Some(inf) = self.inferior.as_mut() {     // after run
-                        let orig_byte = inf.write_byte(b, 0xcc).unwrap();
-                        self.breakpoints.insert(b, orig_byte);
+    /// Reports how the inferior stopped, forgetting about it if it is gone.
+    fn report_status(&mut self, status: Result<inferior::Status, nix::Error>) {
+        match status {
+            Ok(inferior::Status::Exited(ecode)) => {
+                println!("Child exited (status {})", ecode);
+                self.inferior = None;
+                self.switch_to_held();
+            }
+            Ok(inferior::Status::Signaled(signal)) => {
+                println!("Child exited (signal {})", signal);
+                self.inferior = None;
+                self.switch_to_held();
+            }
+            // resume() deals with these without stopping
+            Ok(inferior::Status::Forked(_)) | Ok(inferior::Status::Execed) => {}
+            Ok(inferior::Status::Stopped(signal, ip)) => {
+                // Taken so that a later stop that doesn't go through resume can't report them
+                let hit: Vec<(usize, bool)> = std::mem::take(&mut self.breakpoints_hit)
+                    .into_iter()
+                    .filter_map(|id| self.breakpoints.get(id))
+                    .map(|bp| (bp.id, bp.temporary))
+                    .collect();
+                if signal == Signal::SIGTRAP && !hit.is_empty() {
+                    let ids: Vec<String> = hit.iter().map(|(id, _)| id.to_string()).collect();
+                    if hit.iter().all(|(_, temporary)| *temporary) {
+                        println!("Hit temporary breakpoint {}", ids.join(", "));
                     } else {
-                        self.breakpoints.insert(b, 0);  // before run
+                        println!("Hit breakpoint {}", ids.join(", "));
                     }
+                    for (id, temporary) in hit {
+                        if temporary {
+                            self.delete_breakpoint(id);
+                        }
+                    }
+                } else if signal == Signal::SIGTRAP && self.watchpoint_hit {
+                    // check_watchpoints already said which watchpoints triggered
+                } else {
+                    println!(
+                        "Child stopped (signal {}, {})",
+                        signal,
+                        signals::describe(signal)
+                    );
+                    self.print_fault_address(signal);
+                }
+                if let Some(line) = self.debug_data.get_line_from_addr(ip) {
+                    println!("Stopped at {}", line);
+                }
+                if signal == Signal::SIGSEGV {
+                    self.refresh_frames();
+                    self.print_crash_report();
+                    return;
                 }
             }
+            Err(err) => println!("Error resuming inferior: {}", err),
         }
+        self.refresh_frames();
+    }
+
+    /// Like report_status, but quieter about the SIGTRAP that ends every step.
+    fn report_step_status(&mut self, status: Result<inferior::Status, nix::Error>) {
+        match status {
+            Ok(inferior::Status::Stopped(Signal::SIGTRAP, ip)) => {
+                match self.debug_data.get_line_from_addr(ip) {
+                    Some(line) => println!("Stopped at {}", line),
+                    None => println!("Stopped at {:#x}", ip),
+                }
+                self.refresh_frames();
+            }
+            other => self.report_status(other),
+        }
+    }
+
+    /// Implements "step" and "next".
+    fn step(&mut self, step_into: bool) {
+        if self.inferior.is_none() {
+            println!("no inferior running");
+        } else if let Some(line) = self.current_line() {
+            let status = self.step_line(line, step_into);
+            self.report_step_status(status);
+        }
+    }
+
+    /// Returns the source line the inferior is stopped on, printing an error if it is unknown.
+    fn current_line(&self) -> Option<Line> {
+        let rip = match self.inferior.as_ref().unwrap().get_registers() {
+            Ok(regs) => regs.rip as usize,
+            Err(err) => {
+                println!("Error reading registers: {}", err);
+                return None;
+            }
+        };
+        let line = self.debug_data.get_line_from_addr(rip);
+        if line.is_none() {
+            println!("No line number information for {:#x}", rip);
+        }
+        line
+    }
+
+    /// Single-steps the inferior until it reaches the start of a source line other than
+    /// `start_line`. Calls to functions without debugging information are always stepped over;
+    /// other calls are stepped over unless `step_into` is set.
+    fn step_line(
+        &mut self,
+        mut start_line: Line,
+        step_into: bool,
+    ) -> Result<inferior::Status, nix::Error> {
+        loop {
+            let inf = self.inferior.as_mut().unwrap();
+            let before = inf.get_registers()?;
+            let inserted = self.breakpoints.inserted();
+            let status = inf.step(&inserted, &self.signals)?;
+            let rip = match status {
+                inferior::Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                inferior::Status::Forked(child) => {
+                    self.follow_fork(child, &inserted)?;
+                    continue;
+                }
+                // There is no line to finish stepping in the new program
+                inferior::Status::Execed => {
+                    self.follow_exec();
+                    return self.resume(&[]);
+                }
+                other => return Ok(other),
+            };
+            let after = inf.get_registers()?;
+            if let Some(true) = self.check_watchpoints()? {
+                return Ok(status);
+            }
+            if after.rsp > before.rsp && self.delete_out_of_scope_watchpoints() {
+                return Ok(status);
+            }
+            let inf = self.inferior.as_mut().unwrap();
+
+            // A call pushes the address of the instruction following it
+            if after.rsp == before.rsp - 8 {
+                let return_addr = inf.read_word(after.rsp as usize)?;
+                if return_addr > before.rip as usize && return_addr <= before.rip as usize + 8 {
+                    match self.debug_data.get_line_from_addr(rip) {
+                        Some(line)
+                            if step_into
+                                && self.debug_data.get_function_containing(rip).is_some() =>
+                        {
+                            // Keep stepping through the prologue, until the first line of the body
+                            start_line = line;
+                            continue;
+                        }
+                        _ => {
+                            let status = self.run_to_return(return_addr, before.rsp as usize)?;
+                            match status {
+                                inferior::Status::Stopped(Signal::SIGTRAP, rip)
+                                    if rip == return_addr => {}
+                                other => return Ok(other),
+                            }
+                        }
+                    }
+                }
+            }
+
+            let rip = self.inferior.as_ref().unwrap().get_registers()?.rip as usize;
+            match self.debug_data.get_line_from_addr(rip) {
+                Some(line) => {
+                    if line.number != start_line.number || line.file != start_line.file {
+                        if self.debug_data.is_line_start(rip) {
+                            return Ok(inferior::Status::Stopped(Signal::SIGTRAP, rip));
+                        }
+                    }
+                }
+                // We returned into code we know nothing about (e.g. main returning into libc)
+                None => return self.resume(&[]),
+            }
+        }
+    }
+
+    /// Resumes the inferior until the current function returns, then prints the returned value.
+    fn finish(&mut self) {
+        let inf = match self.inferior.as_mut() {
+            Some(inf) => inf,
+            None => {
+                println!("no inferior running");
+                return;
+            }
+        };
+        let regs = match inf.get_registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Error reading registers: {}", err);
+                return;
+            }
+        };
+        let rip = regs.rip as usize;
+        let func = match self.debug_data.get_function_containing(rip) {
+            Some(func) => func.clone(),
+            None => {
+                println!("Cannot finish: no debugging information for {:#x}", rip);
+                return;
+            }
+        };
+        if func.name == "main" {
+            println!("\"finish\" not meaningful in the outermost frame.");
+            return;
+        }
+        let frames = unwind::backtrace(inf, &mut self.modules);
+        let (return_addr, cfa) = match frames.as_ref().map(|frames| frames.as_slice()) {
+            Ok([frame, caller, ..]) if frame.cfa.is_some() => (caller.pc(), frame.cfa.unwrap()),
+            Ok(_) => {
+                println!("Cannot find the frame that called {}", func.name);
+                return;
+            }
+            Err(err) => {
+                println!("Error unwinding the stack: {}", err);
+                return;
+            }
+        };
+        println!("Run till exit from {}", func.name);
+        let status = self.run_to_return(return_addr, cfa);
+        match status {
+            // Anything else, such as a breakpoint in a recursive call, interrupts the finish
+            Ok(inferior::Status::Stopped(Signal::SIGTRAP, rip)) if rip == return_addr => {
+                self.report_step_status(status);
+                if let Some(return_type) = func.return_type {
+                    self.print_return_value(&return_type);
+                }
+            }
+            other => self.report_status(other),
+        }
+    }
+
+    /// Implements "until" and "advance": runs until the inferior gets to `location`, or the
+    /// selected frame returns. With `same_frame`, stops at the location only in the selected
+    /// frame or one of its callers, not in functions it calls (such as recursive calls).
+    fn advance(&mut self, location: &str, same_frame: bool) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let addr = match self.resolve_breakpoint_location(location) {
+            Ok(addr) => addr,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let frame = match self.selected_frame() {
+            Ok(frame) => *frame,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let cfa = frame.base();
+        let mut targets = vec![addr];
+        let return_addr = self
+            .frames
+            .get(self.selected_frame + 1)
+            .map(|caller| caller.pc());
+        targets.extend(return_addr);
+        let status = loop {
+            let status = self.resume(&targets);
+            let rip = match status {
+                Ok(inferior::Status::Stopped(Signal::SIGTRAP, rip)) => rip,
+                _ => break status,
+            };
+            let inf = self.inferior.as_ref().unwrap();
+            let innermost = match unwind::innermost_frame(inf, &mut self.modules) {
+                Ok(innermost) => innermost,
+                Err(err) => break Err(err),
+            };
+            // The stack grows down, so frames called from this one have lower CFAs
+            let deeper = innermost.base() < cfa;
+            let at_target = rip == addr && !(same_frame && deeper);
+            let returned = Some(rip) == return_addr && innermost.regs.rsp as usize >= cfa;
+            if at_target || returned || !targets.contains(&rip) {
+                break status;
+            }
+        };
+        match status {
+            Ok(inferior::Status::Stopped(Signal::SIGTRAP, rip)) if targets.contains(&rip) => {
+                self.report_step_status(status)
+            }
+            other => self.report_status(other),
+        }
+    }
+
+    /// Prints the value a function just returned, following the System V calling convention.
+    fn print_return_value(&self, return_type: &Type) {
+        let inf = self.inferior.as_ref().unwrap();
+        let base_type = self.debug_data.strip_typedefs(return_type);
+        let is_float = match base_type.kind {
+            TypeKind::Base(Encoding::Float) => true,
+            _ => false,
+        };
+        let bytes = match (inf.get_registers(), inf.get_fp_registers()) {
+            // long double comes back in st0 rather than xmm0
+            (Ok(_), Ok(fpregs)) if is_float && base_type.name == "long double" => {
+                fp_register_bytes(&fpregs.st_space[..4], return_type.size)
+            }
+            (Ok(_), Ok(fpregs)) if is_float && return_type.size <= 16 => {
+                fp_register_bytes(&fpregs.xmm_space[..4], return_type.size)
+            }
+            (_, _) if is_float => {
+                println!("Value returned is <unavailable>");
+                return;
+            }
+            (Ok(regs), _) if return_type.size <= 16 => {
+                let mut bytes = regs.rax.to_le_bytes().to_vec();
+                bytes.extend_from_slice(&regs.rdx.to_le_bytes());
+                bytes.truncate(return_type.size);
+                bytes
+            }
+            // Larger values are returned in memory, with the address in rax
+            (Ok(regs), _) => match inf.read_bytes(regs.rax as usize, return_type.size) {
+                Ok(bytes) => bytes,
+                Err(_) => return,
+            },
+            (Err(_), _) => return,
+        };
+        println!(
+            "Value returned is {}",
+            value::format_value(&self.debug_data, inf, return_type, &bytes)
+        );
+    }
+
+    /// Evaluates an expression in the selected frame and prints its value.
+    fn print_expression(&self, expression: &str) {
+        let inf = match self.inferior.as_ref() {
+            Some(inf) => inf,
+            None => {
+                println!("no inferior running");
+                return;
+            }
+        };
+        let frame = match self.selected_frame() {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let evaluator = Evaluator::new(&self.debug_data, inf, frame);
+        let value = self
+            .parse_expression(expression)
+            .and_then(|expr| evaluator.evaluate(&expr));
+        match value {
+            Ok(value) => println!("{} = {}", expression, evaluator.format(&value)),
+            // Errors like "<optimized out>" stand in for the value itself
+            Err(err) if err.starts_with('<') => println!("{} = {}", expression, err),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Implements `info args` (if `args` is set) and `info locals` for the selected frame.
+    fn print_frame_variables(&self, args: bool) {
+        let inf = match self.inferior.as_ref() {
+            Some(inf) => inf,
+            None => {
+                println!("No frame selected.");
+                return;
+            }
+        };
+        let frame = match self.selected_frame() {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let func = match self.debug_data.get_function_containing(frame.lookup_pc()) {
+            Some(func) => func,
+            None => {
+                println!("No symbol table info available.");
+                return;
+            }
+        };
+        let mut found = false;
+        for var in func.variables.iter().filter(|var| var.is_parameter == args) {
+            found = true;
+            let value = match location::read_variable(&self.debug_data, inf, var, frame) {
+                Ok(bytes) => value::format_value(&self.debug_data, inf, &var.entity_type, &bytes),
+                Err(err) => err,
+            };
+            println!("{} = {}", var.name, value);
+        }
+        if !found {
+            println!("{}", if args { "No arguments." } else { "No locals." });
+        }
+    }
+
+    /// Implements `info functions` (if `functions` is set) and `info variables`, listing
+    /// matching symbols as C declarations grouped by file.
+    fn print_symbols(&self, regex: Option<&str>, functions: bool) {
+        let regex = match Regex::new(regex.unwrap_or("")) {
+            Ok(regex) => regex,
+            Err(err) => {
+                println!("Invalid regular expression: {}", err);
+                return;
+            }
+        };
+        match regex.as_str() {
+            "" if functions => println!("All defined functions:"),
+            "" => println!("All defined variables:"),
+            pattern => println!(
+                "All {} matching regular expression \"{}\":",
+                if functions { "functions" } else { "variables" },
+                pattern
+            ),
+        }
+        for file in self.debug_data.files() {
+            let mut symbols: Vec<(&str, usize, String)> = if functions {
+                file.functions
+                    .iter()
+                    // Declarations of external functions (such as printf) have no code
+                    .filter(|func| func.address != 0 && regex.is_match(&func.name))
+                    .map(|func| {
+                        let params: Vec<String> = func
+                            .variables
+                            .iter()
+                            .filter(|var| var.is_parameter)
+                            .map(|var| declaration(&var.entity_type.name, &var.name))
+                            .collect();
+                        let return_type = func.return_type.as_ref().map_or("void", |ty| &ty.name);
+                        let signature = format!("{}({})", func.name, params.join(", "));
+                        (
+                            func.name.as_str(),
+                            func.line_number,
+                            declaration(return_type, &signature),
+                        )
+                    })
+                    .collect()
+            } else {
+                file.global_variables
+                    .iter()
+                    .filter(|var| regex.is_match(&var.name))
+                    .map(|var| {
+                        let decl = declaration(&var.entity_type.name, &var.name);
+                        (var.name.as_str(), var.line_number, decl)
+                    })
+                    .collect()
+            };
+            if symbols.is_empty() {
+                continue;
+            }
+            symbols.sort();
+            println!("\nFile {}:", file.name);
+            for (_, line_number, decl) in symbols {
+                println!("{}:\t{};", line_number, decl);
+            }
+        }
+    }
+
+    /// Reads the inferior's memory, showing the original bytes in place of our breakpoints.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = self.inferior.as_ref().unwrap().read_bytes(addr, len)?;
+        for (bp_addr, orig_byte) in self.breakpoints.inserted() {
+            if bp_addr >= addr && bp_addr < addr + len {
+                bytes[bp_addr - addr] = orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Works out the address for the `x` command: the value of an expression such as `&var` or
+    /// `p->next`, or the start of an array.
+    fn examine_address(&self, expression: &str) -> Result<usize, String> {
+        let frame = self.selected_frame()?;
+        let evaluator = Evaluator::new(&self.debug_data, self.inferior.as_ref().unwrap(), frame);
+        let value = evaluator.evaluate(&self.parse_expression(expression)?)?;
+        // Arrays decay to their address; structs (which can't) are examined where they are
+        match evaluator.to_integer(&value) {
+            Ok(addr) => Ok(addr as usize),
+            Err(err) => value.address.ok_or(err),
+        }
+    }
+
+    /// Labels an address with the function it is in, e.g. " <main+4>".
+    fn symbolize(&self, addr: usize) -> String {
+        match self.debug_data.get_function_containing(addr) {
+            Some(func) => format!(" <{}+{}>", func.name, addr - func.address),
+            None => String::new(),
+        }
+    }
+
+    /// Dumps memory like gdb's `x` command.
+    fn examine(&self, spec: ExamineSpec, expr: &str) -> Result<(), String> {
+        let mut addr = self.examine_address(expr)?;
+        let cannot_access = |addr: usize| format!("Cannot access memory at address {:#x}", addr);
+        if spec.format == 's' {
+            let inf = self.inferior.as_ref().unwrap();
+            for _ in 0..spec.count {
+                let bytes = value::read_c_string(inf, addr, value::MAX_STRING_LEN)
+                    .ok_or_else(|| cannot_access(addr))?;
+                println!(
+                    "{:#x}{}:\t{}",
+                    addr,
+                    self.symbolize(addr),
+                    value::format_string(&bytes)
+                );
+                addr += bytes.len() + 1;
+            }
+            return Ok(());
+        }
+        let too_large = format!(
+            "Cannot examine more than {} bytes at once.",
+            MAX_EXAMINE_SIZE
+        );
+        let size = spec
+            .count
+            .checked_mul(spec.unit)
+            .filter(|size| *size <= MAX_EXAMINE_SIZE)
+            .ok_or(too_large)?;
+        let bytes = self
+            .read_memory(addr, size)
+            .map_err(|_| cannot_access(addr))?;
+        let per_line = if spec.unit == 8 {
+            2
+        } else if spec.unit == 4 {
+            4
+        } else {
+            8
+        };
+        for (line, chunk) in bytes.chunks(spec.unit * per_line).enumerate() {
+            let line_addr = addr + line * spec.unit * per_line;
+            let values: Vec<String> = chunk
+                .chunks(spec.unit)
+                .map(|unit| match spec.format {
+                    'd' => value::bytes_to_i64(unit).to_string(),
+                    'u' => value::bytes_to_u64(unit).to_string(),
+                    'c' => {
+                        let c = unit[0];
+                        format!("{} '{}'", c as i8, value::format_char(c))
+                    }
+                    _ => {
+                        let width = spec.unit * 2 + 2;
+                        format!("{:#0width$x}", value::bytes_to_u64(unit), width = width)
+                    }
+                })
+                .collect();
+            println!(
+                "{:#x}{}:\t{}",
+                line_addr,
+                self.symbolize(line_addr),
+                values.join("\t")
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the instructions of a function, marking the current instruction with "=>" and
+    /// breakpoints with "b". Code is read from the inferior if there is one (showing the original
+    /// bytes under breakpoints), otherwise from the executable.
+    fn disassemble(&self, location: Option<&str>) -> Result<(), String> {
+        let addr = match location {
+            // Function names like "add" can look like hex numbers too
+            Some(location) if location.starts_with('*') || location.starts_with("0x") => {
+                let location = location.trim_start_matches('*');
+                parse_address(location).ok_or_else(|| format!("Invalid address {}", location))?
+            }
+            Some(location) => self
+                .debug_data
+                .get_addr_for_function(None, location)
+                .ok_or_else(|| format!("No symbol \"{}\" in current context.", location))?,
+            None => match self.selected_frame() {
+                Ok(frame) => frame.lookup_pc(),
+                Err(_) => return Err("No frame selected.".to_string()),
+            },
+        };
+        let func = self
+            .debug_data
+            .get_function_containing(addr)
+            .ok_or_else(|| format!("No function contains specified address {:#x}.", addr))?;
+        let code = match self.inferior {
+            Some(_) => self
+                .read_memory(func.address, func.text_length)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", func.address))?,
+            None => self
+                .debug_data
+                .read_text(func.address, func.text_length)
+                .ok_or_else(|| format!("Cannot access memory at address {:#x}", func.address))?
+                .to_vec(),
+        };
+        let rip = self.selected_frame().ok().map(|frame| frame.pc());
+        let breakpoints = self.breakpoints.inserted();
+
+        println!("Dump of assembler code for function {}:", func.name);
+        let mut decoder = Decoder::new(64, &code, DecoderOptions::NONE);
+        decoder.set_ip(func.address as u64);
+        let mut formatter = GasFormatter::new();
+        formatter.options_mut().set_uppercase_hex(false);
+        while decoder.can_decode() {
+            let instruction = decoder.decode();
+            let ip = instruction.ip() as usize;
+            let mut text = String::new();
+            formatter.format(&instruction, &mut text);
+            let target = instruction.near_branch_target() as usize;
+            if target != 0 {
+                text.push_str(&self.symbolize(target));
+            }
+            let marker = if rip == Some(ip) {
+                "=>"
+            } else if breakpoints.contains_key(&ip) {
+                " b"
+            } else {
+                "  "
+            };
+            println!(
+                "{} {:#018x} <+{}>:\t{}",
+                marker,
+                ip,
+                ip - func.address,
+                text
+            );
+        }
+        println!("End of assembler dump.");
+        Ok(())
+    }
+
+    /// The line the selected frame is at, or the start of main if the inferior isn't running.
+    fn default_list_line(&self) -> Option<Line> {
+        let addr = match self.selected_frame() {
+            Ok(frame) => frame.lookup_pc(),
+            Err(_) => self.debug_data.get_addr_for_function(None, "main")?,
+        };
+        self.debug_data.get_line_from_addr(addr)
+    }
+
+    /// Works out which file and line a `list` argument (`line`, `func`, `file:line` or
+    /// `file:func`) refers to.
+    fn resolve_list_location(&self, location: &str) -> Result<(String, usize), String> {
+        let (file, target) = match location.rfind(':') {
+            Some(index) => (Some(&location[..index]), &location[index + 1..]),
+            None => (None, location),
+        };
+        if let Ok(number) = target.parse::<usize>() {
+            let file = match file {
+                Some(file) => self
+                    .debug_data
+                    .get_line_from_addr(
+                        self.debug_data
+                            .get_addr_for_line(Some(file), number)
+                            .ok_or_else(|| format!("No source file named {}.", file))?,
+                    )
+                    .map(|line| line.file)
+                    .unwrap_or_else(|| file.to_string()),
+                None => match &self.list_position {
+                    Some((file, _)) => file.clone(),
+                    None => {
+                        self.default_list_line()
+                            .ok_or_else(|| "No symbol table is loaded.".to_string())?
+                            .file
+                    }
+                },
+            };
+            return Ok((file, number));
+        }
+        let line = self
+            .debug_data
+            .get_addr_for_function(file, target)
+            .and_then(|addr| self.debug_data.get_line_from_addr(addr))
+            .ok_or_else(|| format!("Function \"{}\" not defined.", target))?;
+        Ok((line.file, line.number))
+    }
+
+    /// Works out the address a `break` argument (`*address`, `line`, `func`, `file:line` or
+    /// `file:func`) refers to. Lines without a file are in the file last listed, or else the one
+    /// being executed.
+    fn resolve_breakpoint_location(&self, location: &str) -> Result<usize, String> {
+        if location.starts_with('*') {
+            return parse_address(&location[1..]).ok_or_else(|| "invalid address".to_string());
+        }
+        let (file, target) = match location.rfind(':') {
+            Some(index) => (Some(&location[..index]), &location[index + 1..]),
+            None => (None, location),
+        };
+        if let Some(file) = file {
+            if !self.debug_data.has_file(file) {
+                return Err(format!("No source file named {}.", file));
+            }
+        }
+        if let Ok(number) = target.parse::<usize>() {
+            let default_file = match &self.list_position {
+                Some((file, _)) => Some(file.clone()),
+                None => self.default_list_line().map(|line| line.file),
+            };
+            let file = file.or_else(|| default_file.as_deref());
+            return self
+                .debug_data
+                .get_addr_for_line(file, number)
+                .ok_or_else(|| match file {
+                    Some(file) => format!("No line {} in file \"{}\".", number, file),
+                    None => format!("No line {} in the current file.", number),
+                });
+        }
+        if file.is_none() {
+            let files = self.debug_data.get_files_defining(target);
+            if files.len() > 1 {
+                return Err(format!(
+                    "Function \"{}\" is defined in several files: {}. Use file:{} to choose one.",
+                    target,
+                    files.join(", "),
+                    target
+                ));
+            }
+        }
+        self.debug_data
+            .get_addr_for_function(file, target)
+            .map(|addr| self.debug_data.get_addr_after_prologue(addr))
+            .ok_or_else(|| match file {
+                Some(file) => format!("Function \"{}\" not defined in \"{}\".", target, file),
+                None => format!("Function \"{}\" not defined.", target),
+            })
+    }
+
+    /// Prints source lines like gdb's `list`, marking the current line with "=>" and lines with
+    /// breakpoints with "b". With no argument, continues where the last listing left off.
+    fn list(&mut self, location: Option<&str>) -> Result<(), String> {
+        let (file, first) = match (location, &self.list_position) {
+            (Some(location), _) => {
+                let (file, center) = self.resolve_list_location(location)?;
+                (file, center.saturating_sub(LIST_SIZE / 2).max(1))
+            }
+            (None, Some((file, next))) => (file.clone(), *next),
+            (None, None) => {
+                let line = self
+                    .default_list_line()
+                    .ok_or_else(|| "No symbol table is loaded.".to_string())?;
+                (line.file, line.number.saturating_sub(LIST_SIZE / 2).max(1))
+            }
+        };
+        let source = std::fs::read_to_string(&file)
+            .map_err(|_| format!("{}: No such file or directory.", file))?;
+        let lines: Vec<&str> = source.lines().collect();
+        if first > lines.len() {
+            return Err(format!(
+                "Line number {} out of range; \"{}\" has {} lines.",
+                first,
+                file,
+                lines.len()
+            ));
+        }
+
+        let current = match self.selected_frame() {
+            Ok(frame) => self.debug_data.get_line_from_addr(frame.lookup_pc()),
+            Err(_) => None,
+        };
+        let breakpoint_lines: Vec<Line> = self
+            .breakpoints
+            .iter()
+            .filter_map(|bp| self.debug_data.get_line_from_addr(bp.addr))
+            .collect();
+        let last = (first + LIST_SIZE - 1).min(lines.len());
+        for number in first..=last {
+            let is_at = |line: &Line| line.file == file && line.number == number;
+            let marker = if current.as_ref().map_or(false, is_at) {
+                "=>"
+            } else if breakpoint_lines.iter().any(is_at) {
+                " b"
+            } else {
+                "  "
+            };
+            println!("{} {:<6}{}", marker, number, lines[number - 1]);
+        }
+        self.list_position = Some((file, last + 1));
+        Ok(())
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
@@ -210,3 +2239,129 @@ impl Debugger {
         }
     }
 }
+
+/// Finds the `=` of an assignment, skipping over comparisons like `==` and `<=`.
+fn assignment_operator(assignment: &str) -> Option<usize> {
+    let bytes = assignment.as_bytes();
+    (0..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && bytes.get(i + 1) != Some(&b'=')
+            && (i == 0 || !b"=!<>".contains(&bytes[i - 1]))
+    })
+}
+
+/// Why accessing an address would segfault, as far as the inferior's memory map can tell.
+#[derive(Debug, PartialEq)]
+enum BadAddress<'a> {
+    /// In the first page, which is never mapped so that dereferencing null (or a field of a
+    /// struct through a null pointer) faults.
+    NullPage,
+    /// In a mapping whose permissions don't allow the access, such as a write to a string
+    /// literal.
+    NotPermitted(&'a inferior::Mapping),
+    /// In the gap the kernel keeps below the stack, which a stack that grows too far runs into.
+    BelowStack,
+    Unmapped,
+}
+
+fn classify_bad_address(addr: usize, mappings: &[inferior::Mapping]) -> BadAddress<'_> {
+    if addr < 0x1000 {
+        return BadAddress::NullPage;
+    }
+    if let Some(mapping) = mappings.iter().find(|m| m.start <= addr && addr < m.end) {
+        return BadAddress::NotPermitted(mapping);
+    }
+    // The gap is 256 pages
+    let stack = mappings.iter().find(|m| m.path == "[stack]");
+    if let Some(stack) = stack {
+        if addr < stack.start && stack.start - addr <= 256 * 0x1000 {
+            return BadAddress::BelowStack;
+        }
+    }
+    BadAddress::Unmapped
+}
+
+/// Explains why accessing `addr` would segfault, given the inferior's memory map.
+fn describe_bad_address(addr: usize, mappings: &[inferior::Mapping]) -> String {
+    match classify_bad_address(addr, mappings) {
+        BadAddress::NullPage => "null pointer dereference".to_string(),
+        BadAddress::NotPermitted(mapping) => {
+            let name = if mapping.path.is_empty() {
+                "anonymous memory"
+            } else {
+                &mapping.path
+            };
+            format!(
+                "not allowed by the {} permissions of {}",
+                mapping.perms, name
+            )
+        }
+        BadAddress::BelowStack => "just below the stack, probably a stack overflow".to_string(),
+        BadAddress::Unmapped => "address not mapped".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Part of the memory map of a small program.
+    const MAPS: &str = "\
+00400000-00401000 r--p 00000000 08:01 1234                               /tmp/crashes
+00401000-00402000 r-xp 00001000 08:01 1234                               /tmp/crashes
+00402000-00403000 r--p 00002000 08:01 1234                               /tmp/crashes
+01a2b000-01a4c000 rw-p 00000000 00:00 0                                  [heap]
+7f1c2d000000-7f1c2d021000 rw-p 00000000 00:00 0 
+7ffd12340000-7ffd12361000 rw-p 00000000 00:00 0                          [stack]
+";
+
+    #[test]
+    fn test_declaration() {
+        assert_eq!(declaration("int", "x"), "int x");
+        assert_eq!(declaration("const char *", "s"), "const char *s");
+        assert_eq!(declaration("int [4]", "a"), "int a[4]");
+        assert_eq!(declaration("int [2][3]", "m"), "int m[2][3]");
+        assert_eq!(declaration("char * [3]", "argv"), "char *argv[3]");
+        assert_eq!(declaration("int (*)()", "f"), "int (*f)()");
+        assert_eq!(declaration("int (*)[4]", "p"), "int (*p)[4]");
+        // Functions are declared by attaching their signature to the return type
+        assert_eq!(declaration("char *", "name(int n)"), "char *name(int n)");
+    }
+
+    #[test]
+    fn test_classify_bad_address() {
+        let mappings = inferior::parse_mappings(MAPS);
+        let classify = |addr| classify_bad_address(addr, &mappings);
+        assert_eq!(classify(0), BadAddress::NullPage);
+        assert_eq!(classify(0x8), BadAddress::NullPage);
+        assert_eq!(classify(0xfff), BadAddress::NullPage);
+        // A write to a string literal in .rodata
+        assert_eq!(classify(0x402010), BadAddress::NotPermitted(&mappings[2]));
+        assert_eq!(
+            classify(0x7f1c2d000000),
+            BadAddress::NotPermitted(&mappings[4])
+        );
+        // One page below the stack, and at the edge of the gap
+        assert_eq!(classify(0x7ffd1233f000), BadAddress::BelowStack);
+        assert_eq!(classify(0x7ffd12240000), BadAddress::BelowStack);
+        assert_eq!(classify(0x7ffd1223ffff), BadAddress::Unmapped);
+        // Just past the end of mappings, and far from any
+        assert_eq!(classify(0x403000), BadAddress::Unmapped);
+        assert_eq!(classify(0x7ffd12361000), BadAddress::Unmapped);
+        assert_eq!(classify(0x12345678), BadAddress::Unmapped);
+        assert_eq!(classify_bad_address(0x1000, &[]), BadAddress::Unmapped);
+    }
+
+    #[test]
+    fn test_describe_bad_address() {
+        let mappings = inferior::parse_mappings(MAPS);
+        assert_eq!(
+            describe_bad_address(0x402010, &mappings),
+            "not allowed by the r--p permissions of /tmp/crashes"
+        );
+        assert_eq!(
+            describe_bad_address(0x7f1c2d000010, &mappings),
+            "not allowed by the rw-p permissions of anonymous memory"
+        );
+    }
+}
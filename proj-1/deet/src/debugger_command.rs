@@ -1,12 +1,135 @@
+use crate::breakpoint::WatchKind;
+use crate::signals::{self, SignalAction};
+use nix::sys::signal::Signal;
+
 pub enum DebuggerCommand {
-    Break(String),
+    Attach(i32),
+    /// A location, and optionally the condition under which to stop there.
+    Break(String, Option<String>),
+    /// Like `Break`, but the breakpoint is deleted once it is hit.
+    Tbreak(String, Option<String>),
+    /// Run until a location is reached, or the current frame returns.
+    Advance(String),
     Backtrace,
+    Condition(usize, Option<String>),
     Continue,
+    Delete(Vec<usize>),
+    Detach,
+    /// Disassemble the current function, or the one named or containing the given address.
+    Disassemble(Option<String>),
+    Disable(Vec<usize>),
+    Enable(Vec<usize>),
+    /// Dump memory at an address (`x/<count><format><unit> <address>`).
+    Examine(ExamineSpec, String),
+    Finish,
+    /// Change what happens when the inferior gets a signal, or just show it (`handle`).
+    Handle(Signal, Vec<SignalAction>),
+    /// Select a frame by number, or show the selected frame.
+    Frame(Option<usize>),
+    /// Select the frame N levels further out (`up`), or in (`down`).
+    Up(usize),
+    Down(usize),
+    Ignore(usize, usize),
+    InfoArgs,
+    InfoBreakpoints,
+    /// List functions, optionally only those matching a regular expression.
+    InfoFunctions(Option<String>),
+    InfoLocals,
+    /// Show how every signal, or just the given one, is handled.
+    InfoSignals(Option<Signal>),
+    InfoThreads,
+    /// Show all registers, or just the named one.
+    InfoRegisters(Option<String>),
+    /// List global variables, optionally only those matching a regular expression.
+    InfoVariables(Option<String>),
+    /// Show source lines around a line or function, or continue the previous listing.
+    List(Option<String>),
+    Next,
+    Print(String),
     Quit,
     Run(Vec<String>),
+    /// An assignment such as `$rax = 5` or `var x = 42`.
+    Set(String),
+    SetFollowForkMode(FollowForkMode),
+    SetDetachOnFork(bool),
+    Step,
+    /// Switch to the thread with the given number, or show the current thread.
+    Thread(Option<usize>),
+    /// Like `Advance`, but not stopping at the location in frames called from the current one.
+    Until(String),
+    Watch(String, WatchKind),
+}
+
+/// Which process to keep debugging when the inferior forks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FollowForkMode {
+    Parent,
+    Child,
+}
+
+/// How the `x` command shows memory.
+#[derive(Debug, Clone, Copy)]
+pub struct ExamineSpec {
+    /// Number of units (or strings) to show.
+    pub count: usize,
+    /// 'x' (hex), 'd' (signed decimal), 'u' (unsigned decimal), 'c' (char) or 's' (string).
+    pub format: char,
+    /// Size of a unit in bytes: 1 (b), 2 (h), 4 (w) or 8 (g).
+    pub unit: usize,
+}
+
+/// Parses the part of an `x` command after the slash, e.g. "4xw".
+fn parse_examine_spec(spec: &str) -> Option<ExamineSpec> {
+    let digits: String = spec.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let count = if digits.is_empty() {
+        1
+    } else {
+        digits.parse().ok()?
+    };
+    let mut format = None;
+    let mut unit = None;
+    for c in spec[digits.len()..].chars() {
+        match c {
+            'x' | 'd' | 'u' | 'c' | 's' => format = Some(c),
+            'b' => unit = Some(1),
+            'h' => unit = Some(2),
+            'w' => unit = Some(4),
+            'g' => unit = Some(8),
+            _ => return None,
+        }
+    }
+    let format = format.unwrap_or('x');
+    // Like gdb, characters default to single bytes rather than words
+    let default_unit = if format == 'c' { 1 } else { 4 };
+    Some(ExamineSpec {
+        count,
+        format,
+        unit: unit.unwrap_or(default_unit),
+    })
+}
+
+/// Parses a list of breakpoint numbers.
+fn parse_ids(tokens: &[&str]) -> Option<Vec<usize>> {
+    tokens.iter().map(|token| token.parse().ok()).collect()
 }
 
 impl DebuggerCommand {
+    /// Whether the command runs or changes the inferior, which can't be done to a core file.
+    pub fn needs_process(&self) -> bool {
+        matches!(
+            self,
+            DebuggerCommand::Continue
+                | DebuggerCommand::Step
+                | DebuggerCommand::Next
+                | DebuggerCommand::Finish
+                | DebuggerCommand::Until(_)
+                | DebuggerCommand::Advance(_)
+                | DebuggerCommand::Set(_)
+                | DebuggerCommand::Watch(_, _)
+                | DebuggerCommand::Detach
+        )
+    }
+
     pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
@@ -16,17 +139,193 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             },
+            "attach" => Some(DebuggerCommand::Attach(tokens.get(1)?.parse().ok()?)),
+            "detach" => Some(DebuggerCommand::Detach),
+            "disas" | "disassemble" => Some(DebuggerCommand::Disassemble(
+                tokens.get(1).map(|location| location.to_string()),
+            )),
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => {
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "l" | "list" => Some(DebuggerCommand::List(tokens.get(1).map(|s| s.to_string()))),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
+            "thread" => match tokens.get(1) {
+                Some(id) => Some(DebuggerCommand::Thread(Some(id.parse().ok()?))),
+                None => Some(DebuggerCommand::Thread(None)),
+            },
+            "f" | "frame" => match tokens.get(1) {
+                Some(level) => Some(DebuggerCommand::Frame(Some(level.parse().ok()?))),
+                None => Some(DebuggerCommand::Frame(None)),
+            },
+            "up" | "down" => {
+                let count = match tokens.get(1) {
+                    Some(count) => count.parse().ok()?,
+                    None => 1,
+                };
+                if tokens[0] == "up" {
+                    Some(DebuggerCommand::Up(count))
+                } else {
+                    Some(DebuggerCommand::Down(count))
+                }
+            }
+            "i" | "info" => match tokens.get(1) {
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some(DebuggerCommand::InfoBreakpoints)
+                }
+                Some(&"args") => Some(DebuggerCommand::InfoArgs),
+                Some(&"locals") => Some(DebuggerCommand::InfoLocals),
+                Some(&"threads") => Some(DebuggerCommand::InfoThreads),
+                Some(&"signals") | Some(&"handle") => match tokens.get(2) {
+                    Some(name) => Some(DebuggerCommand::InfoSignals(Some(signals::parse_signal(
+                        name,
+                    )?))),
+                    None => Some(DebuggerCommand::InfoSignals(None)),
+                },
+                Some(&"functions") => Some(DebuggerCommand::InfoFunctions(
+                    tokens.get(2).map(|regex| regex.to_string()),
+                )),
+                Some(&"variables") => Some(DebuggerCommand::InfoVariables(
+                    tokens.get(2).map(|regex| regex.to_string()),
+                )),
+                Some(&"r") | Some(&"reg") | Some(&"registers") => Some(
+                    DebuggerCommand::InfoRegisters(tokens.get(2).map(|name| name.to_string())),
+                ),
+                _ => None,
+            },
+            "set" if tokens.get(1) == Some(&"follow-fork-mode") => match tokens.get(2) {
+                Some(&"parent") => Some(DebuggerCommand::SetFollowForkMode(FollowForkMode::Parent)),
+                Some(&"child") => Some(DebuggerCommand::SetFollowForkMode(FollowForkMode::Child)),
+                _ => None,
+            },
+            "set" if tokens.get(1) == Some(&"detach-on-fork") => match tokens.get(2) {
+                Some(&"on") => Some(DebuggerCommand::SetDetachOnFork(true)),
+                Some(&"off") => Some(DebuggerCommand::SetDetachOnFork(false)),
+                _ => None,
+            },
+            "set" => {
+                // `set var x = 1` is the same as `set x = 1`
+                let start = match tokens.get(1) {
+                    Some(&"var") | Some(&"variable") => 2,
+                    _ => 1,
+                };
+                if tokens.len() <= start {
+                    return None;
+                }
+                Some(DebuggerCommand::Set(tokens[start..].join(" ")))
+            }
+            "handle" => {
+                let signal = signals::parse_signal(tokens.get(1)?)?;
+                let actions = tokens[2..]
+                    .iter()
+                    .map(|keyword| SignalAction::from_keyword(keyword))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(DebuggerCommand::Handle(signal, actions))
+            }
+            "condition" => {
+                let id = tokens.get(1)?.parse().ok()?;
+                if tokens.len() == 2 {
+                    Some(DebuggerCommand::Condition(id, None))
+                } else {
+                    Some(DebuggerCommand::Condition(id, Some(tokens[2..].join(" "))))
+                }
+            }
+            "ignore" => {
+                if tokens.len() != 3 {
+                    return None;
+                }
+                Some(DebuggerCommand::Ignore(
+                    tokens[1].parse().ok()?,
+                    tokens[2].parse().ok()?,
+                ))
+            }
+            "watch" | "rwatch" | "awatch" => {
                 if tokens.len() == 1 {
                     return None;
+                }
+                let kind = match tokens[0] {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                Some(DebuggerCommand::Watch(tokens[1..].join(" "), kind))
+            }
+            "d" | "delete" => Some(DebuggerCommand::Delete(parse_ids(&tokens[1..])?)),
+            "disable" => Some(DebuggerCommand::Disable(parse_ids(&tokens[1..])?)),
+            "enable" => Some(DebuggerCommand::Enable(parse_ids(&tokens[1..])?)),
+            "b" | "break" | "tbreak" => {
+                let condition = if tokens.len() == 1 {
+                    return None;
+                } else if tokens.len() == 2 {
+                    None
+                } else if tokens[2] == "if" && tokens.len() > 3 {
+                    Some(tokens[3..].join(" "))
                 } else {
-                    return Some(DebuggerCommand::Break(tokens[1].to_string()));
+                    return None;
+                };
+                if tokens[0] == "tbreak" {
+                    Some(DebuggerCommand::Tbreak(tokens[1].to_string(), condition))
+                } else {
+                    Some(DebuggerCommand::Break(tokens[1].to_string(), condition))
+                }
+            }
+            "u" | "until" if tokens.len() == 2 => {
+                Some(DebuggerCommand::Until(tokens[1].to_string()))
+            }
+            "advance" if tokens.len() == 2 => Some(DebuggerCommand::Advance(tokens[1].to_string())),
+            "p" | "print" => {
+                if tokens.len() == 1 {
+                    None
+                } else {
+                    Some(DebuggerCommand::Print(tokens[1..].join(" ")))
+                }
+            }
+            command if command == "x" || command.starts_with("x/") => {
+                if tokens.len() == 1 {
+                    return None;
                 }
-           }
+                let spec = parse_examine_spec(command.get(2..).unwrap_or(""))?;
+                Some(DebuggerCommand::Examine(spec, tokens[1..].join(" ")))
+            }
             // Default case:
             _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec(spec: &str) -> (usize, char, usize) {
+        let spec = parse_examine_spec(spec).expect("Expected the spec to parse");
+        (spec.count, spec.format, spec.unit)
+    }
+
+    #[test]
+    fn test_examine_spec_defaults() {
+        assert_eq!(spec(""), (1, 'x', 4));
+        assert_eq!(spec("c"), (1, 'c', 1));
+        assert_eq!(spec("3d"), (3, 'd', 4));
+    }
+
+    #[test]
+    fn test_examine_spec_explicit() {
+        assert_eq!(spec("4xg"), (4, 'x', 8));
+        assert_eq!(spec("2ub"), (2, 'u', 1));
+        assert_eq!(spec("hc"), (1, 'c', 2));
+        assert_eq!(spec("s"), (1, 's', 4));
+        assert_eq!(spec("2s"), (2, 's', 4));
+    }
+
+    #[test]
+    fn test_examine_spec_invalid() {
+        assert!(parse_examine_spec("4q").is_none());
+        assert!(parse_examine_spec("x4").is_none());
+        assert!(parse_examine_spec("-1x").is_none());
+        assert!(
+            parse_examine_spec("99999999999999999999999x").is_none(),
+            "A count too big for usize should be rejected rather than panic"
+        );
+    }
+}
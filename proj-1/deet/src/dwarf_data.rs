@@ -1,6 +1,7 @@
 use crate::gimli_wrapper;
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::{fmt, fs};
 
@@ -12,7 +13,10 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
+    types: HashMap<usize, Type>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// The address and contents of the .text section, for disassembling without a process.
+    text: Option<(usize, Vec<u8>)>,
 }
 
 impl fmt::Debug for DwarfData {
@@ -38,20 +42,69 @@ impl DwarfData {
         } else {
             gimli::RunTimeEndian::Big
         };
+        let (files, types) = gimli_wrapper::load_file(&object, endian)?;
+        let text = object.section_by_name(".text").map(|section| {
+            (
+                section.address() as usize,
+                section.uncompressed_data().into_owned(),
+            )
+        });
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
+            files,
+            types,
             addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            text,
         })
     }
 
-    #[allow(dead_code)]
+    /// Builds debugging information out of the given files and types, as if read from an
+    /// executable with no other sections, for testing code that uses it.
+    #[cfg(test)]
+    pub fn from_parts(files: Vec<File>, types: HashMap<usize, Type>) -> DwarfData {
+        // An ELF header for an x86-64 executable with no sections or segments
+        let mut header = vec![0x7f, b'E', b'L', b'F', 2, 1, 1];
+        header.resize(16, 0);
+        header.extend_from_slice(&[2, 0, 62, 0, 1, 0, 0, 0]);
+        header.resize(52, 0);
+        header.extend_from_slice(&[64, 0, 56, 0, 0, 0, 64, 0, 0, 0, 0, 0]);
+        let object = object::File::parse(&header[..]).unwrap();
+        DwarfData {
+            files,
+            types,
+            addr2line: Context::new(&object).unwrap(),
+            text: None,
+        }
+    }
+
+    /// Finds a compilation unit by its path, or by any trailing part of its path (so `util.c`
+    /// and `lib/util.c` both find `/home/me/src/lib/util.c`). Units are named by the path they
+    /// were compiled with, which may be relative, so a full path also finds `lib/util.c`.
     fn get_target_file(&self, file: &str) -> Option<&File> {
         self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+            f.name == file
+                || f.name.ends_with(&format!("/{}", file))
+                || file.ends_with(&format!("/{}", f.name))
         })
     }
 
-    #[allow(dead_code)]
+    pub fn has_file(&self, file: &str) -> bool {
+        self.get_target_file(file).is_some()
+    }
+
+    /// Returns the names of the compilation units that define a function with this name.
+    /// Static functions can have the same name in several of them.
+    pub fn get_files_defining(&self, func_name: &str) -> Vec<&str> {
+        self.files
+            .iter()
+            .filter(|file| {
+                file.functions
+                    .iter()
+                    .any(|func| func.name == func_name && func.address != 0)
+            })
+            .map(|file| file.name.as_str())
+            .collect()
+    }
+
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
@@ -66,19 +119,21 @@ impl DwarfData {
         )
     }
 
-    #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        // Declarations of external functions, and the abstract descriptions of inlined ones,
+        // have no code of their own
+        let is_match = |func: &&Function| func.name == func_name && func.address != 0;
         match file {
             Some(filename) => Some(
                 self.get_target_file(filename)?
                     .functions
                     .iter()
-                    .find(|func| func.name == func_name)?
+                    .find(is_match)?
                     .address,
             ),
             None => {
                 for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
+                    if let Some(func) = file.functions.iter().find(is_match) {
                         return Some(func.address);
                     }
                 }
@@ -87,7 +142,6 @@ impl DwarfData {
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
@@ -100,7 +154,6 @@ impl DwarfData {
         })
     }
 
-    #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
         let frame = self
             .addr2line
@@ -111,6 +164,111 @@ impl DwarfData {
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
+    /// Returns the function whose text contains the given address.
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= addr && addr < func.address + func.text_length)
+    }
+
+    /// Returns the address where the function starting at `func_addr` is done setting up its
+    /// stack frame, which is where a breakpoint on the function should go so that its arguments
+    /// and locals can be read. Like gdb, this takes the prologue to be everything up to the
+    /// first line table row for a different line than the function's opening line; optimized
+    /// code often has no prologue, and then this is `func_addr` itself.
+    pub fn get_addr_after_prologue(&self, func_addr: usize) -> usize {
+        let func = match self.get_function_containing(func_addr) {
+            Some(func) => func,
+            None => return func_addr,
+        };
+        let mut rows: Vec<&Line> = self
+            .files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .filter(|line| {
+                func.address <= line.address && line.address < func.address + func.text_length
+            })
+            .collect();
+        // A stable sort, so rows for the same address stay in line table order
+        rows.sort_by_key(|line| line.address);
+        match rows.first() {
+            Some(first) if first.address == func_addr => rows
+                .iter()
+                .find(|line| line.number != first.number)
+                .map_or(func_addr, |line| line.address),
+            _ => func_addr,
+        }
+    }
+
+    /// Returns true if `addr` is the first instruction of a row in the line table, i.e. a place
+    /// where a new source line starts.
+    pub fn is_line_start(&self, addr: usize) -> bool {
+        self.files
+            .iter()
+            .any(|file| file.lines.iter().any(|line| line.address == addr))
+    }
+
+    pub fn files(&self) -> &[File] {
+        &self.files
+    }
+
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Looks up a variable, first among the locals and parameters of the function containing
+    /// `pc`, then among the globals.
+    pub fn find_variable(&self, name: &str, pc: usize) -> Option<&Variable> {
+        let local = self
+            .get_function_containing(pc)
+            .and_then(|func| func.variables.iter().find(|var| var.name == name));
+        local.or_else(|| self.get_global_variable(name))
+    }
+
+    /// Looks up a type by name, such as `struct node` or a typedef's name.
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        // Prefer complete types to declarations like `struct node;`, which have no size
+        self.types
+            .values()
+            .filter(|ty| ty.name == name)
+            .max_by_key(|ty| ty.size)
+    }
+
+    /// Returns `len` bytes of the executable's code starting at `addr`, as they are on disk.
+    pub fn read_text(&self, addr: usize, len: usize) -> Option<&[u8]> {
+        let (start, data) = self.text.as_ref()?;
+        let offset = addr.checked_sub(*start)?;
+        data.get(offset..offset + len)
+    }
+
+    /// Follows typedefs, const and volatile to the type underneath, e.g. from `node_t` to
+    /// `struct node`.
+    pub fn strip_typedefs<'a>(&'a self, ty: &'a Type) -> &'a Type {
+        let mut ty = ty;
+        // Bounded in case of a malformed cycle
+        for _ in 0..16 {
+            match ty.kind {
+                TypeKind::Typedef(Some(target))
+                | TypeKind::Const(Some(target))
+                | TypeKind::Volatile(Some(target)) => match self.get_type(target) {
+                    Some(target) => ty = target,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        ty
+    }
+
+    /// Looks up a type by its offset in the .debug_info section (as stored in `TypeKind`).
+    pub fn get_type(&self, offset: usize) -> Option<&Type> {
+        self.types.get(&offset)
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         for file in &self.files {
@@ -152,32 +310,99 @@ impl DwarfData {
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
-        }
+    pub fn with_kind(name: String, size: usize, kind: TypeKind) -> Self {
+        Type { name, size, kind }
     }
 }
 
+/// Types refer to other types by their offset in the .debug_info section; use
+/// `DwarfData::get_type` to look them up.
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    /// A base type such as `int` or `char`.
+    Base(Encoding),
+    /// A pointer. Contains the type pointed to, or None for `void *`.
+    Pointer(Option<usize>),
+    /// An array. Contains the element type and the number of elements.
+    Array(usize, usize),
+    /// A struct or union. Contains its members, in declaration order.
+    Struct(Vec<Member>),
+    /// An enum. Contains the names and values of its enumerators.
+    Enum(Vec<(String, i64)>),
+    /// A typedef. Contains the type it names, or None for `void`.
+    Typedef(Option<usize>),
+    /// A const version of another type (None for `const void`).
+    Const(Option<usize>),
+    /// A volatile version of another type.
+    Volatile(Option<usize>),
+    /// The type of a function, which is only ever seen through function pointers. Contains the
+    /// return type, or None for `void`.
+    Function(Option<usize>),
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Base(Encoding::Signed)
+    }
+}
+
+/// How the bits of a base type are interpreted (DW_AT_encoding).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Boolean,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub type_offset: usize,
+    pub offset: usize, // Byte offset from the start of the struct
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Any other DWARF location expression, such as a register or a value split into pieces. See
+    /// `location::locate`.
+    Expression(Expression),
+    /// A location list: (start, end, expression) for each range of pc values. Optimized code
+    /// moves variables between registers and the stack, and a variable is optimized out at pcs
+    /// that no range covers.
+    List(Vec<(usize, usize, Expression)>),
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression) => {
+                write!(f, "Expression({} bytes)", expression.bytes.len())
+            }
+            Location::List(entries) => write!(f, "List({} entries)", entries.len()),
         }
     }
 }
 
+/// The raw bytes of a DWARF expression, which can only be evaluated once the registers and
+/// memory of a running inferior are available.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub bytes: Vec<u8>,
+    /// The address size and DWARF version of the unit the expression came from.
+    pub encoding: gimli::Encoding,
+}
+
 impl fmt::Debug for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Display::fmt(self, f)
@@ -191,6 +416,7 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -200,6 +426,9 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub return_type: Option<Type>, // None for void functions
+    /// DW_AT_frame_base, which DW_OP_fbreg offsets are relative to. See `location::frame_base`.
+    pub frame_base: Option<Location>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -223,4 +452,77 @@ impl fmt::Display for Line {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(number: usize, address: usize) -> Line {
+        Line {
+            file: "prog.c".to_string(),
+            number,
+            address,
+        }
+    }
+
+    fn function(name: &str, address: usize, text_length: usize) -> Function {
+        Function {
+            name: name.to_string(),
+            address,
+            text_length,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_target_file() {
+        let file = |name: &str| File {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        const UTIL: &str = "/home/me/src/lib/util.c";
+        let files = vec![file(UTIL), file("lib/io.c"), file("main.c")];
+        let debug_data = DwarfData::from_parts(files, HashMap::new());
+        let found = |name: &str| debug_data.get_target_file(name).map(|f| f.name.as_str());
+        assert_eq!(found("util.c"), Some(UTIL));
+        assert_eq!(found("lib/util.c"), Some(UTIL));
+        assert_eq!(found(UTIL), Some(UTIL));
+        // A unit compiled with a relative path is found by a full path ending in it
+        assert_eq!(found("/home/me/src/lib/io.c"), Some("lib/io.c"));
+        assert_eq!(found("io.c"), Some("lib/io.c"));
+        // Only whole path components match
+        assert_eq!(found("il.c"), None);
+        assert_eq!(found("domain.c"), None);
+        assert_eq!(found("src/util.c"), None);
+    }
 
+    #[test]
+    fn test_addr_after_prologue() {
+        let file = File {
+            name: "prog.c".to_string(),
+            functions: vec![
+                function("main", 0x1000, 0x40),
+                function("tiny", 0x2000, 0x10),
+                function("split", 0x3000, 0x40),
+            ],
+            // main's opening line has two rows; split's rows are out of order
+            lines: vec![
+                line(5, 0x1000),
+                line(5, 0x1008),
+                line(6, 0x1010),
+                line(7, 0x1020),
+                line(10, 0x2000),
+                line(21, 0x3020),
+                line(20, 0x3000),
+                line(22, 0x3030),
+            ],
+            ..Default::default()
+        };
+        let debug_data = DwarfData::from_parts(vec![file], HashMap::new());
+        assert_eq!(debug_data.get_addr_after_prologue(0x1000), 0x1010);
+        assert_eq!(debug_data.get_addr_after_prologue(0x3000), 0x3020);
+        // A function with a single row has no prologue to skip
+        assert_eq!(debug_data.get_addr_after_prologue(0x2000), 0x2000);
+        // Nor has an address outside every function
+        assert_eq!(debug_data.get_addr_after_prologue(0x5000), 0x5000);
+    }
+}
@@ -0,0 +1,905 @@
+//! Evaluation of C expressions (as parsed by `expr`) against the inferior's registers and memory,
+//! using the types from the debugging information.
+
+use crate::dwarf_data::{DwarfData, Encoding, Member, Type, TypeKind};
+use crate::expr::{BinaryOp, Expr, TypeName, UnaryOp};
+use crate::inferior::{self, Inferior};
+use crate::location::{self, Place};
+use crate::unwind::Frame;
+use crate::value;
+use std::convert::TryInto;
+
+/// The words that make up the names of C's built-in types.
+const BUILTIN_TYPE_WORDS: [&str; 10] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+];
+
+const NOT_A_NUMBER: &str = "Argument to arithmetic operation not a number or boolean.";
+const NOT_IN_MEMORY: &str = "Attempt to take address of value not located in memory.";
+
+/// The type of a value. Most come from the debugging information, but `&` and casts can create
+/// pointer types that the program itself never uses.
+#[derive(Debug, Clone)]
+pub enum ValueType {
+    Type(Type),
+    Pointer(Box<ValueType>),
+    Void,
+}
+
+impl ValueType {
+    pub fn name(&self) -> String {
+        match self {
+            ValueType::Type(ty) => ty.name.clone(),
+            ValueType::Pointer(target) => {
+                let target = target.name();
+                if target.ends_with('*') {
+                    format!("{}*", target)
+                } else {
+                    format!("{} *", target)
+                }
+            }
+            ValueType::Void => "void".to_string(),
+        }
+    }
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub ty: ValueType,
+    pub bytes: Vec<u8>,
+    /// Where the value is in the inferior's memory, if it is there at all (as opposed to in a
+    /// register, or computed by the expression).
+    pub address: Option<usize>,
+}
+
+/// What a value's type amounts to once typedefs and qualifiers are seen through.
+enum Class {
+    Integer {
+        signed: bool,
+    },
+    Float,
+    Pointer(ValueType),
+    /// An array, with its element type.
+    Array(ValueType),
+    Struct(Vec<Member>),
+    /// void, functions, and anything else that can't be operated on.
+    Other,
+}
+
+fn base_type(name: &str, size: usize, encoding: Encoding) -> ValueType {
+    ValueType::Type(Type::with_kind(
+        name.to_string(),
+        size,
+        TypeKind::Base(encoding),
+    ))
+}
+
+fn integer(value: i64, size: usize, signed: bool) -> Value {
+    let (name, encoding) = match (size, signed) {
+        (8, true) => ("long int", Encoding::Signed),
+        (8, false) => ("long unsigned int", Encoding::Unsigned),
+        (_, true) => ("int", Encoding::Signed),
+        (_, false) => ("unsigned int", Encoding::Unsigned),
+    };
+    Value {
+        ty: base_type(name, size, encoding),
+        bytes: value.to_le_bytes()[..size].to_vec(),
+        address: None,
+    }
+}
+
+fn boolean(value: bool) -> Value {
+    integer(value as i64, 4, true)
+}
+
+fn double(value: f64) -> Value {
+    Value {
+        ty: base_type("double", 8, Encoding::Float),
+        bytes: value.to_le_bytes().to_vec(),
+        address: None,
+    }
+}
+
+fn pointer(target: ValueType, addr: usize) -> Value {
+    Value {
+        ty: ValueType::Pointer(Box::new(target)),
+        bytes: addr.to_le_bytes().to_vec(),
+        address: None,
+    }
+}
+
+/// The integer promotions: anything smaller than an int is operated on as an int.
+fn promote(size: usize, signed: bool) -> (usize, bool) {
+    if size < 4 {
+        (4, true)
+    } else {
+        (size.min(8), signed)
+    }
+}
+
+/// Works out one of C's built-in types from the words of its name, e.g. "unsigned long". Names
+/// are given the way gcc writes them in the debugging information.
+fn builtin_type(words: &[&str]) -> ValueType {
+    let has = |word| words.contains(&word);
+    let unsigned = has("unsigned");
+    let longs = words.iter().filter(|word| **word == "long").count();
+    let (name, size, encoding) = if has("void") {
+        return ValueType::Void;
+    } else if has("char") {
+        if unsigned {
+            ("unsigned char", 1, Encoding::UnsignedChar)
+        } else if has("signed") {
+            ("signed char", 1, Encoding::SignedChar)
+        } else {
+            ("char", 1, Encoding::SignedChar)
+        }
+    } else if has("_Bool") {
+        ("_Bool", 1, Encoding::Boolean)
+    } else if has("float") {
+        ("float", 4, Encoding::Float)
+    } else if has("double") && longs > 0 {
+        ("long double", 16, Encoding::Float)
+    } else if has("double") {
+        ("double", 8, Encoding::Float)
+    } else if has("short") {
+        if unsigned {
+            ("short unsigned int", 2, Encoding::Unsigned)
+        } else {
+            ("short int", 2, Encoding::Signed)
+        }
+    } else if longs > 1 {
+        if unsigned {
+            ("long long unsigned int", 8, Encoding::Unsigned)
+        } else {
+            ("long long int", 8, Encoding::Signed)
+        }
+    } else if longs == 1 {
+        if unsigned {
+            ("long unsigned int", 8, Encoding::Unsigned)
+        } else {
+            ("long int", 8, Encoding::Signed)
+        }
+    } else if unsigned {
+        ("unsigned int", 4, Encoding::Unsigned)
+    } else {
+        ("int", 4, Encoding::Signed)
+    };
+    base_type(name, size, encoding)
+}
+
+/// Evaluates expressions in the context of one stack frame.
+pub struct Evaluator<'a> {
+    debug_data: &'a DwarfData,
+    inferior: &'a Inferior,
+    frame: &'a Frame,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(debug_data: &'a DwarfData, inferior: &'a Inferior, frame: &'a Frame) -> Self {
+        Evaluator {
+            debug_data,
+            inferior,
+            frame,
+        }
+    }
+
+    pub fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Integer(value, suffix) => {
+                // Like in C, literals are ints unless they don't fit in one or have an l suffix,
+                // and are unsigned if they have a u suffix
+                let fits = if suffix.unsigned {
+                    *value as u32 as i64 == *value
+                } else {
+                    *value as i32 as i64 == *value
+                };
+                let size = if fits && !suffix.long { 4 } else { 8 };
+                Ok(integer(*value, size, !suffix.unsigned))
+            }
+            Expr::Float(value) => Ok(double(*value)),
+            Expr::Char(c) => Ok(Value {
+                ty: base_type("char", 1, Encoding::SignedChar),
+                bytes: vec![*c],
+                address: None,
+            }),
+            Expr::Register(name) => self.register(name),
+            Expr::Variable(name) => self.variable(name),
+            Expr::Unary(op, operand) => self.unary(*op, self.evaluate(operand)?),
+            // && and || only evaluate their right-hand side if they need to
+            Expr::Binary(BinaryOp::And, lhs, rhs) => Ok(boolean(
+                self.is_true(&self.evaluate(lhs)?)? && self.is_true(&self.evaluate(rhs)?)?,
+            )),
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => Ok(boolean(
+                self.is_true(&self.evaluate(lhs)?)? || self.is_true(&self.evaluate(rhs)?)?,
+            )),
+            Expr::Binary(op, lhs, rhs) => {
+                self.binary(*op, self.evaluate(lhs)?, self.evaluate(rhs)?)
+            }
+            Expr::Index(array, index) => self.index(self.evaluate(array)?, self.evaluate(index)?),
+            Expr::Member(value, member) => self.member(self.evaluate(value)?, member),
+            Expr::Cast(type_name, value) => {
+                let ty = self.resolve_type_name(type_name)?;
+                self.cast(self.evaluate(value)?, ty)
+            }
+        }
+    }
+
+    /// Says whether a value counts as true in a condition (i.e. is nonzero).
+    pub fn is_true(&self, value: &Value) -> Result<bool, String> {
+        match self.classify(&value.ty) {
+            Class::Float => Ok(self.to_float(value)? != 0.0),
+            _ => Ok(self.to_integer(value)? != 0),
+        }
+    }
+
+    /// Formats a value the way `print` shows it.
+    pub fn format(&self, value: &Value) -> String {
+        match &value.ty {
+            ValueType::Type(ty) => {
+                value::format_value(self.debug_data, self.inferior, ty, &value.bytes)
+            }
+            ValueType::Pointer(target) => {
+                let target = match &**target {
+                    ValueType::Type(ty) => Some(ty),
+                    _ => None,
+                };
+                let addr = value::bytes_to_u64(&value.bytes) as usize;
+                value::format_pointer(
+                    self.debug_data,
+                    self.inferior,
+                    &value.ty.name(),
+                    target,
+                    addr,
+                )
+            }
+            ValueType::Void => "void".to_string(),
+        }
+    }
+
+    /// Interprets a value as an integer, the way C does in arithmetic: pointers are addresses,
+    /// and arrays decay to the address of their first element.
+    pub fn to_integer(&self, value: &Value) -> Result<i64, String> {
+        match self.classify(&value.ty) {
+            Class::Integer { signed: true } => Ok(value::bytes_to_i64(&value.bytes)),
+            Class::Integer { signed: false } | Class::Pointer(_) => {
+                Ok(value::bytes_to_u64(&value.bytes) as i64)
+            }
+            Class::Float => Ok(self.to_float(value)? as i64),
+            Class::Array(_) => value
+                .address
+                .map(|addr| addr as i64)
+                .ok_or_else(|| NOT_IN_MEMORY.to_string()),
+            _ => Err("Value can't be converted to integer.".to_string()),
+        }
+    }
+
+    fn to_float(&self, value: &Value) -> Result<f64, String> {
+        match self.classify(&value.ty) {
+            Class::Float => match value.bytes.len() {
+                4 => Ok(f32::from_le_bytes(value.bytes[..].try_into().unwrap()) as f64),
+                8 => Ok(f64::from_le_bytes(value.bytes[..].try_into().unwrap())),
+                _ => Err("Unsupported floating-point size.".to_string()),
+            },
+            Class::Integer { signed: false } => Ok(self.to_integer(value)? as u64 as f64),
+            _ => Ok(self.to_integer(value)? as f64),
+        }
+    }
+
+    fn classify(&self, ty: &ValueType) -> Class {
+        let ty = match ty {
+            ValueType::Type(ty) => self.debug_data.strip_typedefs(ty),
+            ValueType::Pointer(target) => return Class::Pointer((**target).clone()),
+            ValueType::Void => return Class::Other,
+        };
+        let lookup = |offset: Option<usize>| {
+            offset
+                .and_then(|offset| self.debug_data.get_type(offset))
+                .map_or(ValueType::Void, |ty| ValueType::Type(ty.clone()))
+        };
+        match &ty.kind {
+            TypeKind::Base(Encoding::Float) => Class::Float,
+            TypeKind::Base(Encoding::Signed)
+            | TypeKind::Base(Encoding::SignedChar)
+            | TypeKind::Enum(_) => Class::Integer { signed: true },
+            TypeKind::Base(_) => Class::Integer { signed: false },
+            TypeKind::Pointer(target) => Class::Pointer(lookup(*target)),
+            TypeKind::Array(elem, _) => Class::Array(lookup(Some(*elem))),
+            TypeKind::Struct(members) => Class::Struct(members.clone()),
+            _ => Class::Other,
+        }
+    }
+
+    fn is_bool(&self, ty: &ValueType) -> bool {
+        match ty {
+            ValueType::Type(ty) => matches!(
+                self.debug_data.strip_typedefs(ty).kind,
+                TypeKind::Base(Encoding::Boolean)
+            ),
+            _ => false,
+        }
+    }
+
+    fn size_of(&self, ty: &ValueType) -> usize {
+        match ty {
+            ValueType::Type(ty) => ty.size,
+            ValueType::Pointer(_) => 8,
+            // Like gcc, treat void as having size 1 for pointer arithmetic
+            ValueType::Void => 1,
+        }
+    }
+
+    fn read(&self, ty: ValueType, addr: usize) -> Result<Value, String> {
+        let bytes = self
+            .inferior
+            .read_bytes(addr, self.size_of(&ty))
+            .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+        Ok(Value {
+            ty,
+            bytes,
+            address: Some(addr),
+        })
+    }
+
+    fn register(&self, name: &str) -> Result<Value, String> {
+        let register = match name {
+            "pc" => "rip",
+            "sp" => "rsp",
+            "fp" => "rbp",
+            name => name,
+        };
+        let value = inferior::get_register(&self.frame.regs, register)
+            .ok_or_else(|| format!("Invalid register \"${}\"", name))?;
+        if !self.frame.has_register(register) {
+            return Err("<not saved>".to_string());
+        }
+        Ok(integer(value as i64, 8, true))
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, String> {
+        let var = self
+            .debug_data
+            .find_variable(name, self.frame.lookup_pc())
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+        let ty = ValueType::Type(var.entity_type.clone());
+        match location::locate(self.debug_data, self.inferior, var, self.frame)? {
+            Place::Memory(addr) => self.read(ty, addr),
+            Place::Value(bytes) => Ok(Value {
+                ty,
+                bytes,
+                address: None,
+            }),
+        }
+    }
+
+    fn unary(&self, op: UnaryOp, value: Value) -> Result<Value, String> {
+        match (op, self.classify(&value.ty)) {
+            (UnaryOp::Neg, Class::Float) => Ok(double(-self.to_float(&value)?)),
+            (UnaryOp::Neg, Class::Integer { signed }) => {
+                let (size, signed) = promote(value.bytes.len(), signed);
+                Ok(integer(
+                    self.to_integer(&value)?.wrapping_neg(),
+                    size,
+                    signed,
+                ))
+            }
+            (UnaryOp::BitNot, Class::Integer { signed }) => {
+                let (size, signed) = promote(value.bytes.len(), signed);
+                Ok(integer(!self.to_integer(&value)?, size, signed))
+            }
+            (UnaryOp::Neg, _) | (UnaryOp::BitNot, _) => Err(NOT_A_NUMBER.to_string()),
+            (UnaryOp::Not, _) => Ok(boolean(!self.is_true(&value)?)),
+            (UnaryOp::Deref, _) => self.deref(value),
+            (UnaryOp::AddressOf, _) => match value.address {
+                Some(addr) => Ok(pointer(value.ty, addr)),
+                None => Err(NOT_IN_MEMORY.to_string()),
+            },
+        }
+    }
+
+    fn deref(&self, value: Value) -> Result<Value, String> {
+        match self.classify(&value.ty) {
+            Class::Pointer(ValueType::Void) => {
+                Err("Attempt to take contents of a non-pointer value.".to_string())
+            }
+            Class::Pointer(target) => self.read(target, value::bytes_to_u64(&value.bytes) as usize),
+            Class::Array(_) => self.index(value, integer(0, 4, true)),
+            _ => Err("Attempt to take contents of a non-pointer value.".to_string()),
+        }
+    }
+
+    fn index(&self, array: Value, index: Value) -> Result<Value, String> {
+        let index = match self.classify(&index.ty) {
+            Class::Integer { .. } => self.to_integer(&index)?,
+            _ => return Err(NOT_A_NUMBER.to_string()),
+        };
+        let (elem, base) = match self.classify(&array.ty) {
+            Class::Array(elem) => match array.address {
+                Some(addr) => (elem, addr),
+                // An array held in registers: its elements have to come out of its bytes
+                None => {
+                    let size = self.size_of(&elem);
+                    let start = (index as usize).wrapping_mul(size);
+                    let bytes = match array.bytes.get(start..start.saturating_add(size)) {
+                        Some(bytes) if index >= 0 => bytes.to_vec(),
+                        _ => return Err("no such vector element".to_string()),
+                    };
+                    return Ok(Value {
+                        ty: elem,
+                        bytes,
+                        address: None,
+                    });
+                }
+            },
+            Class::Pointer(ValueType::Void) => {
+                return Err("Attempt to take contents of a non-pointer value.".to_string());
+            }
+            Class::Pointer(target) => (target, value::bytes_to_u64(&array.bytes) as usize),
+            _ => {
+                return Err(format!(
+                    "cannot subscript something of type `{}'",
+                    array.ty.name()
+                ));
+            }
+        };
+        let offset = index.wrapping_mul(self.size_of(&elem) as i64);
+        self.read(elem, (base as i64).wrapping_add(offset) as usize)
+    }
+
+    fn member(&self, value: Value, name: &str) -> Result<Value, String> {
+        // `.` and `->` are interchangeable, so pointers to structs are dereferenced either way
+        let value = match self.classify(&value.ty) {
+            Class::Pointer(_) => self.deref(value)?,
+            _ => value,
+        };
+        let members = match self.classify(&value.ty) {
+            Class::Struct(members) => members,
+            _ => {
+                return Err(
+                    "Attempt to extract a component of a value that is not a structure."
+                        .to_string(),
+                );
+            }
+        };
+        let member = members
+            .iter()
+            .find(|member| member.name == name)
+            .ok_or_else(|| format!("There is no member named {}.", name))?;
+        let ty = self
+            .debug_data
+            .get_type(member.type_offset)
+            .cloned()
+            .ok_or_else(|| format!("Unknown type for member {}.", name))?;
+        let bytes = value
+            .bytes
+            .get(member.offset..member.offset + ty.size)
+            .ok_or_else(|| format!("Member {} lies outside its structure.", name))?
+            .to_vec();
+        Ok(Value {
+            ty: ValueType::Type(ty),
+            bytes,
+            address: value.address.map(|addr| addr + member.offset),
+        })
+    }
+
+    fn binary(&self, op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, String> {
+        let is_comparison = match op {
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => true,
+            BinaryOp::Eq | BinaryOp::Ne => true,
+            _ => false,
+        };
+        // The type pointed to, for pointers and (decayed) arrays
+        let pointer_target = |value: &Value| match self.classify(&value.ty) {
+            Class::Pointer(target) | Class::Array(target) => Some(target),
+            _ => None,
+        };
+        match (pointer_target(&lhs), pointer_target(&rhs)) {
+            (Some(target), None) if op == BinaryOp::Add || op == BinaryOp::Sub => {
+                let offset = self
+                    .to_integer(&rhs)?
+                    .wrapping_mul(self.size_of(&target) as i64);
+                let addr = if op == BinaryOp::Add {
+                    self.to_integer(&lhs)?.wrapping_add(offset)
+                } else {
+                    self.to_integer(&lhs)?.wrapping_sub(offset)
+                };
+                return Ok(pointer(target, addr as usize));
+            }
+            (None, Some(target)) if op == BinaryOp::Add => {
+                let offset = self
+                    .to_integer(&lhs)?
+                    .wrapping_mul(self.size_of(&target) as i64);
+                return Ok(pointer(
+                    target,
+                    self.to_integer(&rhs)?.wrapping_add(offset) as usize,
+                ));
+            }
+            (Some(target), Some(_)) if op == BinaryOp::Sub => {
+                let diff = self.to_integer(&lhs)?.wrapping_sub(self.to_integer(&rhs)?);
+                return Ok(integer(diff / self.size_of(&target).max(1) as i64, 8, true));
+            }
+            (Some(_), _) | (_, Some(_)) if is_comparison => {
+                let (a, b) = (self.to_integer(&lhs)? as u64, self.to_integer(&rhs)? as u64);
+                return Ok(boolean(compare(op, a, b)));
+            }
+            (Some(_), _) | (_, Some(_)) => return Err(NOT_A_NUMBER.to_string()),
+            (None, None) => {}
+        }
+
+        let (lsigned, rsigned) = match (self.classify(&lhs.ty), self.classify(&rhs.ty)) {
+            (Class::Integer { signed: lsigned }, Class::Integer { signed: rsigned }) => {
+                (lsigned, rsigned)
+            }
+            (Class::Integer { .. }, Class::Float)
+            | (Class::Float, Class::Integer { .. })
+            | (Class::Float, Class::Float) => {
+                let (a, b) = (self.to_float(&lhs)?, self.to_float(&rhs)?);
+                return match op {
+                    BinaryOp::Mul => Ok(double(a * b)),
+                    BinaryOp::Div => Ok(double(a / b)),
+                    BinaryOp::Add => Ok(double(a + b)),
+                    BinaryOp::Sub => Ok(double(a - b)),
+                    op if is_comparison => Ok(boolean(compare(op, a, b))),
+                    _ => Err("Integer only operation.".to_string()),
+                };
+            }
+            _ => return Err(NOT_A_NUMBER.to_string()),
+        };
+
+        // The usual arithmetic conversions: the result has the larger of the two (promoted)
+        // sizes, and is unsigned if an operand of that size is
+        let (lsize, lsigned) = promote(lhs.bytes.len(), lsigned);
+        let (rsize, rsigned) = promote(rhs.bytes.len(), rsigned);
+        let size = lsize.max(rsize);
+        let signed = !((lsize == size && !lsigned) || (rsize == size && !rsigned));
+        let mask = if size == 8 {
+            !0
+        } else {
+            (1u64 << (size * 8)) - 1
+        };
+        let (a, b) = (self.to_integer(&lhs)?, self.to_integer(&rhs)?);
+        let (ua, ub) = (a as u64 & mask, b as u64 & mask);
+        if is_comparison {
+            return Ok(boolean(if signed {
+                compare(op, a, b)
+            } else {
+                compare(op, ua, ub)
+            }));
+        }
+        if (op == BinaryOp::Div || op == BinaryOp::Rem) && ub == 0 {
+            return Err("Division by zero".to_string());
+        }
+        let result = match op {
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div if signed => a.wrapping_div(b),
+            BinaryOp::Div => (ua / ub) as i64,
+            BinaryOp::Rem if signed => a.wrapping_rem(b),
+            BinaryOp::Rem => (ua % ub) as i64,
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Shl => a.wrapping_shl(b as u32),
+            BinaryOp::Shr if signed => a.wrapping_shr(b as u32),
+            BinaryOp::Shr => ua.wrapping_shr(b as u32) as i64,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::BitOr => a | b,
+            _ => unreachable!(),
+        };
+        Ok(integer(result, size, signed))
+    }
+
+    fn resolve_type_name(&self, type_name: &TypeName) -> Result<ValueType, String> {
+        let words: Vec<&str> = type_name.base.split(' ').collect();
+        let mut ty = if words.iter().all(|word| BUILTIN_TYPE_WORDS.contains(word)) {
+            builtin_type(&words)
+        } else {
+            match self.debug_data.get_type_by_name(&type_name.base) {
+                Some(ty) => ValueType::Type(ty.clone()),
+                None => {
+                    return Err(format!(
+                        "No symbol \"{}\" in current context.",
+                        type_name.base
+                    ))
+                }
+            }
+        };
+        for _ in 0..type_name.pointers {
+            ty = ValueType::Pointer(Box::new(ty));
+        }
+        Ok(ty)
+    }
+
+    /// Converts a value to the type of `target`, for assigning it there. Structs can only be
+    /// assigned from values of the same type; everything else converts as in a cast.
+    pub fn convert_for_assignment(&self, value: Value, target: &Value) -> Result<Value, String> {
+        match self.classify(&target.ty) {
+            Class::Integer { .. } | Class::Pointer(_) | Class::Float => {
+                self.cast(value, target.ty.clone())
+            }
+            _ if value.ty.name() == target.ty.name() => Ok(value),
+            _ => Err("Invalid cast.".to_string()),
+        }
+    }
+
+    fn cast(&self, value: Value, ty: ValueType) -> Result<Value, String> {
+        let size = self.size_of(&ty);
+        let bytes = match self.classify(&ty) {
+            // Like in C, anything nonzero converts to a _Bool of 1, rather than being truncated
+            Class::Integer { .. } if self.is_bool(&ty) => vec![self.is_true(&value)? as u8],
+            Class::Integer { .. } | Class::Pointer(_) => {
+                self.to_integer(&value)?.to_le_bytes()[..size.min(8)].to_vec()
+            }
+            Class::Float if size == 4 => (self.to_float(&value)? as f32).to_le_bytes().to_vec(),
+            Class::Float if size == 8 => self.to_float(&value)?.to_le_bytes().to_vec(),
+            _ => return Err("Invalid cast.".to_string()),
+        };
+        Ok(Value {
+            ty,
+            bytes,
+            address: None,
+        })
+    }
+}
+
+fn compare<T: PartialOrd>(op: BinaryOp, a: T, b: T) -> bool {
+    match op {
+        BinaryOp::Lt => a < b,
+        BinaryOp::Le => a <= b,
+        BinaryOp::Gt => a > b,
+        BinaryOp::Ge => a >= b,
+        BinaryOp::Eq => a == b,
+        _ => a != b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coredump::CoreDump;
+    use crate::dwarf_data::{File, Location, Variable};
+    use crate::expr;
+    use std::collections::HashMap;
+
+    const INT: usize = 1;
+    const NODE: usize = 2;
+    const NODE_POINTER: usize = 3;
+    const INT_ARRAY: usize = 4;
+
+    fn global(name: &str, ty: &Type, addr: usize) -> Variable {
+        Variable {
+            name: name.to_string(),
+            entity_type: ty.clone(),
+            location: Location::Address(addr),
+            line_number: 1,
+            is_parameter: false,
+        }
+    }
+
+    fn node(value: i32, next: u64) -> Vec<u8> {
+        let mut bytes = value.to_le_bytes().to_vec();
+        bytes.resize(8, 0);
+        bytes.extend_from_slice(&next.to_le_bytes());
+        bytes
+    }
+
+    /// Runs `f` with an evaluator for a program with these globals:
+    ///
+    ///     struct node { int value; struct node *next; };
+    ///     struct node *list;    // at 0x1000, pointing to the list 5 -> 7 at 0x2000
+    ///     int nums[3];          // at 0x3000, containing 10, 20, 30
+    fn with_evaluator<T>(f: impl FnOnce(&Evaluator) -> T) -> T {
+        let mut types = HashMap::new();
+        let int = Type::with_kind("int".to_string(), 4, TypeKind::Base(Encoding::Signed));
+        let members = vec![
+            Member {
+                name: "value".to_string(),
+                type_offset: INT,
+                offset: 0,
+            },
+            Member {
+                name: "next".to_string(),
+                type_offset: NODE_POINTER,
+                offset: 8,
+            },
+        ];
+        let node_type = Type::with_kind("struct node".to_string(), 16, TypeKind::Struct(members));
+        let node_pointer = Type::with_kind(
+            "struct node *".to_string(),
+            8,
+            TypeKind::Pointer(Some(NODE)),
+        );
+        let int_array = Type::with_kind("int [3]".to_string(), 12, TypeKind::Array(INT, 3));
+        let file = File {
+            name: "list.c".to_string(),
+            global_variables: vec![
+                global("list", &node_pointer, 0x1000),
+                global("nums", &int_array, 0x3000),
+            ],
+            ..Default::default()
+        };
+        types.insert(INT, int);
+        types.insert(NODE, node_type);
+        types.insert(NODE_POINTER, node_pointer);
+        types.insert(INT_ARRAY, int_array);
+        let debug_data = DwarfData::from_parts(vec![file], types);
+
+        let mut nodes = node(5, 0x2010);
+        nodes.extend(node(7, 0));
+        let nums: Vec<u8> = [10i32, 20, 30]
+            .iter()
+            .flat_map(|n| n.to_le_bytes().to_vec())
+            .collect();
+        let inferior = Inferior::with_core(CoreDump::from_regions(&[
+            (0x1000, &0x2000u64.to_le_bytes()),
+            (0x2000, &nodes),
+            (0x3000, &nums),
+        ]));
+        let frame = Frame {
+            level: 0,
+            regs: unsafe { std::mem::zeroed() },
+            cfa: None,
+        };
+        f(&Evaluator::new(&debug_data, &inferior, &frame))
+    }
+
+    fn evaluate(text: &str) -> Result<Value, String> {
+        with_evaluator(|evaluator| evaluator.evaluate(&expr::parse(text, &|_| false)?))
+    }
+
+    /// Evaluates an expression to an integer (or address), along with its size and signedness.
+    fn evaluate_integer(text: &str) -> (i64, usize, bool) {
+        with_evaluator(|evaluator| {
+            let value = evaluator
+                .evaluate(&expr::parse(text, &|_| false).unwrap())
+                .unwrap();
+            let signed = match evaluator.classify(&value.ty) {
+                Class::Integer { signed } => signed,
+                Class::Pointer(_) => false,
+                _ => panic!("{} is not an integer", text),
+            };
+            (
+                evaluator.to_integer(&value).unwrap(),
+                value.bytes.len(),
+                signed,
+            )
+        })
+    }
+
+    fn int(text: &str) -> i64 {
+        evaluate_integer(text).0
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(int("1 - 2 - 3"), -4);
+        assert_eq!(int("2 + 3 * 4"), 14);
+        assert_eq!(int("(2 + 3) * 4"), 20);
+        assert_eq!(int("-2 - -3"), 1);
+        assert_eq!(int("7 / 2"), 3);
+        assert_eq!(int("-7 / 2"), -3);
+        assert_eq!(int("-7 % 2"), -1);
+        assert_eq!(int("1 << 4 | 1"), 17);
+        assert_eq!(int("~0"), -1);
+        assert_eq!(int("!5 + !0"), 1);
+        assert_eq!(int("0 && 1 / 0"), 0);
+        assert_eq!(int("1 || 1 / 0"), 1);
+        // Results wrap at the size of the promoted type
+        assert_eq!(evaluate_integer("2147483647 + 1"), (-2147483648, 4, true));
+        assert_eq!(evaluate_integer("4294967295u + 1"), (0, 4, false));
+        assert_eq!(evaluate_integer("2147483647L + 1"), (2147483648, 8, true));
+    }
+
+    #[test]
+    fn test_signed_unsigned_comparison() {
+        // -1 converts to a huge unsigned int when compared with one
+        assert_eq!(int("-1 < 1u"), 0);
+        assert_eq!(int("-1 < 1"), 1);
+        assert_eq!(int("-1 == 4294967295u"), 1);
+        // ... but an unsigned int converts to long, keeping its value
+        assert_eq!(int("-1L < 1u"), 1);
+        assert_eq!(int("-1 < 1ul"), 0);
+        assert_eq!(evaluate_integer("-1 / 2u"), (2147483647, 4, false));
+        assert_eq!(evaluate_integer("-1 >> 1"), (-1, 4, true));
+        assert_eq!(evaluate_integer("-1u >> 1"), (2147483647, 4, false));
+    }
+
+    #[test]
+    fn test_integer_promotion() {
+        // chars and shorts are operated on as ints, whatever their signedness
+        assert_eq!(evaluate_integer("'a' + 1"), (98, 4, true));
+        assert_eq!(evaluate_integer("(char)200"), (-56, 1, true));
+        assert_eq!(evaluate_integer("(char)200 + 1"), (-55, 4, true));
+        assert_eq!(evaluate_integer("(unsigned char)255 + 1"), (256, 4, true));
+        assert_eq!(
+            evaluate_integer("(unsigned short)65535 * 2"),
+            (131070, 4, true)
+        );
+        assert_eq!(evaluate_integer("-(unsigned char)1"), (-1, 4, true));
+        assert_eq!(int("(unsigned short)1 > -1"), 1);
+        assert_eq!(int("(short)-1 < 1u"), 0);
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("1 % 0").is_err());
+        assert!(evaluate("1u / 0").is_err());
+        assert!(evaluate("1L % (2 - 2)").is_err());
+        // Only the low bits of the divisor count once it is converted
+        assert!(evaluate("1 / (int)4294967296").is_err());
+        assert!(evaluate("(-2147483647 - 1) / -1").is_ok());
+        assert!(evaluate("(-2147483647 - 1) % -1").is_ok());
+    }
+
+    #[test]
+    fn test_members_and_indexing() {
+        assert_eq!(int("list->value"), 5);
+        assert_eq!(int("list.value"), 5);
+        assert_eq!(int("(*list).value"), 5);
+        assert_eq!(int("list->next->value"), 7);
+        assert_eq!(int("list[1].value"), 7);
+        assert_eq!(int("(list + 1)->value"), 7);
+        assert_eq!(int("list->next->next"), 0);
+        assert_eq!(int("&list->next->value"), 0x2010);
+        assert_eq!(int("nums[0] + nums[2]"), 40);
+        assert_eq!(int("*(nums + 1)"), 20);
+        assert_eq!(int("&nums[2] - nums"), 2);
+        assert_eq!(int("nums[list->value - 4]"), 20);
+        assert!(evaluate("list->nope").is_err());
+        assert!(evaluate("nums.value").is_err());
+        assert!(evaluate("list->value[0]").is_err());
+        // Following the null pointer at the end of the list
+        assert!(evaluate("list->next->next->value").is_err());
+    }
+
+    #[test]
+    fn test_registers_of_outer_frames() {
+        let debug_data = DwarfData::from_parts(Vec::new(), HashMap::new());
+        let inferior = Inferior::with_core(CoreDump::from_regions(&[]));
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rax = 5;
+        regs.rbx = 7;
+        let mut frame = Frame {
+            level: 0,
+            regs,
+            cfa: None,
+        };
+        let register = |frame: &Frame, name: &str| {
+            let evaluator = Evaluator::new(&debug_data, &inferior, frame);
+            let value = evaluator.evaluate(&expr::parse(name, &|_| false)?)?;
+            evaluator.to_integer(&value)
+        };
+        assert_eq!(register(&frame, "$rax"), Ok(5));
+        // Only callee-saved registers are known in the caller
+        frame.level = 1;
+        assert_eq!(register(&frame, "$rbx"), Ok(7));
+        assert_eq!(register(&frame, "$rax"), Err("<not saved>".to_string()));
+    }
+
+    #[test]
+    fn test_bool_conversion() {
+        assert_eq!(evaluate("(_Bool)0").unwrap().bytes, vec![0]);
+        assert_eq!(evaluate("(_Bool)1").unwrap().bytes, vec![1]);
+        assert_eq!(evaluate("(_Bool)2").unwrap().bytes, vec![1]);
+        assert_eq!(evaluate("(_Bool)256").unwrap().bytes, vec![1]);
+        assert_eq!(evaluate("(_Bool)-1").unwrap().bytes, vec![1]);
+        assert_eq!(evaluate("(_Bool)0.5").unwrap().bytes, vec![1]);
+        // Assigning to a _Bool variable converts the same way
+        let flag = Value {
+            ty: builtin_type(&["_Bool"]),
+            bytes: vec![0],
+            address: None,
+        };
+        with_evaluator(|evaluator| {
+            let convert = |value| {
+                evaluator
+                    .convert_for_assignment(value, &flag)
+                    .unwrap()
+                    .bytes
+            };
+            assert_eq!(convert(integer(2, 4, true)), vec![1]);
+            assert_eq!(convert(integer(256, 4, true)), vec![1]);
+            assert_eq!(convert(integer(0, 4, true)), vec![0]);
+        });
+    }
+}
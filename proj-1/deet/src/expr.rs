@@ -0,0 +1,574 @@
+//! Parsing of C expressions, such as `p->next->val * 2` or `$rax == 0`, for `print` and breakpoint
+//! conditions. See `eval` for how they are evaluated.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Integer(i64, IntegerSuffix),
+    Float(f64),
+    Char(u8),
+    /// A register, named without the leading `$`.
+    Register(String),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `array[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `value.member` or `value->member`, which are treated the same way (as gdb does).
+    Member(Box<Expr>, String),
+    Cast(TypeName, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// The suffix of an integer literal, such as the `ul` of `1ul`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IntegerSuffix {
+    pub unsigned: bool,
+    pub long: bool,
+}
+
+/// The type in a cast, such as `unsigned long` or `struct node *`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeName {
+    /// The type without its pointers, with any `const` or `volatile` dropped.
+    pub base: String,
+    /// The number of `*`s.
+    pub pointers: usize,
+}
+
+/// Words that can only start a type name, as opposed to typedef names, which need looking up.
+const TYPE_KEYWORDS: [&str; 15] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+    "struct", "union", "enum", "const", "volatile",
+];
+
+// Longer operators come first so that "<=" isn't mistaken for "<"
+const PUNCTUATION: [&str; 26] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "(", ")", "[", "]", ".",
+];
+
+/// Binary operators with their precedence (higher binds more tightly).
+const BINARY_OPERATORS: [(&str, BinaryOp, u8); 18] = [
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Rem, 10),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("<", BinaryOp::Lt, 7),
+    ("<=", BinaryOp::Le, 7),
+    (">", BinaryOp::Gt, 7),
+    (">=", BinaryOp::Ge, 7),
+    ("==", BinaryOp::Eq, 6),
+    ("!=", BinaryOp::Ne, 6),
+    ("&", BinaryOp::BitAnd, 5),
+    ("^", BinaryOp::BitXor, 4),
+    ("|", BinaryOp::BitOr, 3),
+    ("&&", BinaryOp::And, 2),
+    ("||", BinaryOp::Or, 1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Integer(i64, IntegerSuffix),
+    Float(f64),
+    Char(u8),
+    Ident(String),
+    Register(String),
+    Punct(&'static str),
+}
+
+fn parse_char_literal(s: &str) -> Option<(u8, usize)> {
+    let bytes = s.as_bytes();
+    let (c, len) = match bytes.get(1)? {
+        b'\\' => {
+            let c = match bytes.get(2)? {
+                b'n' => b'\n',
+                b't' => b'\t',
+                b'r' => b'\r',
+                b'0' => 0,
+                other => *other,
+            };
+            (c, 4)
+        }
+        c => (*c, 3),
+    };
+    if bytes.get(len - 1)? == &b'\'' {
+        Some((c, len))
+    } else {
+        None
+    }
+}
+
+fn parse_number(s: &str) -> Result<(Token, usize), String> {
+    let len = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+        .unwrap_or_else(|| s.len());
+    let text = &s[..len];
+    let invalid = || format!("Invalid number \"{}\".", text);
+    let is_suffix = |c: char| c == 'u' || c == 'U' || c == 'l' || c == 'L';
+    let digits = text.trim_end_matches(is_suffix);
+    let suffix = &text[digits.len()..];
+    let suffix = IntegerSuffix {
+        unsigned: suffix.contains('u') || suffix.contains('U'),
+        long: suffix.contains('l') || suffix.contains('L'),
+    };
+    if text.starts_with("0x") || text.starts_with("0X") {
+        let value = u64::from_str_radix(&digits[2..], 16).map_err(|_| invalid())?;
+        Ok((Token::Integer(value as i64, suffix), len))
+    } else if text.contains('.') || text.contains('e') || text.contains('E') {
+        let value = text.trim_end_matches(|c| c == 'f' || c == 'F').parse();
+        Ok((Token::Float(value.map_err(|_| invalid())?), len))
+    } else {
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        Ok((Token::Integer(value as i64, suffix), len))
+    }
+}
+
+/// Splits an expression into tokens, each with its offset in `s` (for error messages).
+fn tokenize(s: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < s.len() {
+        let rest = &s[pos..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        let (token, len) = if c.is_ascii_digit() {
+            parse_number(rest)?
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let len = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |len| len + 1);
+            if c == '$' {
+                (Token::Register(rest[1..len].to_string()), len)
+            } else {
+                (Token::Ident(rest[..len].to_string()), len)
+            }
+        } else if c == '\'' {
+            let (c, len) =
+                parse_char_literal(rest).ok_or_else(|| "Unmatched single quote.".to_string())?;
+            (Token::Char(c), len)
+        } else {
+            match PUNCTUATION.iter().find(|punct| rest.starts_with(*punct)) {
+                Some(punct) => (Token::Punct(punct), punct.len()),
+                None => return Err(format!("Invalid character '{}' in expression.", c)),
+            }
+        };
+        tokens.push((token, pos));
+        pos += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Says whether a name is a typedef, which decides whether `(name)` starts a cast.
+    is_type: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn syntax_error(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some((_, offset)) => format!(
+                "A syntax error in expression, near `{}'.",
+                &self.text[*offset..]
+            ),
+            None => "A syntax error in expression, near `'.".to_string(),
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        match self.peek() {
+            Some(Token::Punct(p)) if *p == punct => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// Says whether the next tokens are the `(type)` of a cast.
+    fn at_cast(&self) -> bool {
+        match (self.peek(), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Punct("(")), Some((Token::Ident(name), _))) => {
+                TYPE_KEYWORDS.contains(&name.as_str()) || (self.is_type)(name)
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_type_name(&mut self) -> Result<TypeName, String> {
+        let mut words: Vec<String> = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek().cloned() {
+            let after_tag = match words.last() {
+                Some(last) => last == "struct" || last == "union" || last == "enum",
+                None => false,
+            };
+            if !(TYPE_KEYWORDS.contains(&word.as_str()) || after_tag || words.is_empty()) {
+                break;
+            }
+            self.pos += 1;
+            if word != "const" && word != "volatile" {
+                words.push(word);
+            }
+        }
+        let mut pointers = 0;
+        while let Some(Token::Punct("*")) = self.peek() {
+            self.pos += 1;
+            pointers += 1;
+        }
+        if words.is_empty() {
+            return Err(self.syntax_error());
+        }
+        Ok(TypeName {
+            base: words.join(" "),
+            pointers,
+        })
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Punct(punct)) => BINARY_OPERATORS
+                    .iter()
+                    .find(|(text, _, precedence)| text == punct && *precedence >= min_precedence),
+                _ => None,
+            };
+            let (_, op, precedence) = match operator {
+                Some(operator) => *operator,
+                None => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_binary(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.at_cast() {
+            self.pos += 1;
+            let type_name = self.parse_type_name()?;
+            self.expect(")")?;
+            return Ok(Expr::Cast(type_name, Box::new(self.parse_unary()?)));
+        }
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => UnaryOp::Neg,
+            Some(Token::Punct("!")) => UnaryOp::Not,
+            Some(Token::Punct("~")) => UnaryOp::BitNot,
+            Some(Token::Punct("*")) => UnaryOp::Deref,
+            Some(Token::Punct("&")) => UnaryOp::AddressOf,
+            Some(Token::Punct("+")) => {
+                self.pos += 1;
+                return self.parse_unary();
+            }
+            _ => return self.parse_postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Punct("[")) => {
+                    self.pos += 1;
+                    let index = self.parse_binary(0)?;
+                    self.expect("]")?;
+                    expr = Expr::Index(Box::new(expr), Box::new(index));
+                }
+                Some(Token::Punct(".")) | Some(Token::Punct("->")) => {
+                    self.pos += 1;
+                    match self.next() {
+                        Some(Token::Ident(member)) => expr = Expr::Member(Box::new(expr), member),
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.syntax_error());
+                        }
+                    }
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Integer(value, suffix)) => Ok(Expr::Integer(value, suffix)),
+            Some(Token::Float(value)) => Ok(Expr::Float(value)),
+            Some(Token::Char(c)) => Ok(Expr::Char(c)),
+            Some(Token::Register(name)) => Ok(Expr::Register(name)),
+            Some(Token::Ident(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Punct("(")) => {
+                let expr = self.parse_binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.syntax_error())
+            }
+        }
+    }
+}
+
+/// Parses an expression. `is_type` says whether a name is a typedef, since `(name) x` is only a
+/// cast if it is.
+pub fn parse(s: &str, is_type: &dyn Fn(&str) -> bool) -> Result<Expr, String> {
+    let mut parser = Parser {
+        text: s,
+        tokens: tokenize(s)?,
+        pos: 0,
+        is_type,
+    };
+    if parser.tokens.is_empty() {
+        return Err("Argument required (expression to compute).".to_string());
+    }
+    let expr = parser.parse_binary(0)?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.syntax_error());
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_expr(s: &str) -> Expr {
+        parse(s, &|name| name == "node_t").unwrap()
+    }
+
+    fn int(value: i64) -> Box<Expr> {
+        Box::new(Expr::Integer(value, IntegerSuffix::default()))
+    }
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    fn binary(op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr>) -> Box<Expr> {
+        Box::new(Expr::Binary(op, lhs, rhs))
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse_expr("a + b * c"),
+            *binary(
+                BinaryOp::Add,
+                var("a"),
+                binary(BinaryOp::Mul, var("b"), var("c"))
+            )
+        );
+        assert_eq!(
+            parse_expr("a * b + c"),
+            *binary(
+                BinaryOp::Add,
+                binary(BinaryOp::Mul, var("a"), var("b")),
+                var("c")
+            )
+        );
+        assert_eq!(
+            parse_expr("(a + b) * c"),
+            *binary(
+                BinaryOp::Mul,
+                binary(BinaryOp::Add, var("a"), var("b")),
+                var("c")
+            )
+        );
+        assert_eq!(
+            parse_expr("a == b && c < d || e"),
+            *binary(
+                BinaryOp::Or,
+                binary(
+                    BinaryOp::And,
+                    binary(BinaryOp::Eq, var("a"), var("b")),
+                    binary(BinaryOp::Lt, var("c"), var("d"))
+                ),
+                var("e")
+            )
+        );
+    }
+
+    #[test]
+    fn test_associativity() {
+        assert_eq!(
+            parse_expr("1 - 2 - 3"),
+            *binary(BinaryOp::Sub, binary(BinaryOp::Sub, int(1), int(2)), int(3))
+        );
+        assert_eq!(
+            parse_expr("8 / 4 / 2"),
+            *binary(BinaryOp::Div, binary(BinaryOp::Div, int(8), int(4)), int(2))
+        );
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        let neg = |expr| Box::new(Expr::Unary(UnaryOp::Neg, expr));
+        assert_eq!(parse_expr("-a"), *neg(var("a")));
+        assert_eq!(
+            parse_expr("a - -b"),
+            *binary(BinaryOp::Sub, var("a"), neg(var("b")))
+        );
+        assert_eq!(parse_expr("a-1"), *binary(BinaryOp::Sub, var("a"), int(1)));
+        // Unary minus binds more tightly than any binary operator
+        assert_eq!(
+            parse_expr("-a * b"),
+            *binary(BinaryOp::Mul, neg(var("a")), var("b"))
+        );
+        assert_eq!(
+            parse_expr("*p - 1"),
+            *binary(
+                BinaryOp::Sub,
+                Box::new(Expr::Unary(UnaryOp::Deref, var("p"))),
+                int(1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_cast_or_parentheses() {
+        let type_name = |base: &str, pointers| TypeName {
+            base: base.to_string(),
+            pointers,
+        };
+        assert_eq!(
+            parse_expr("(int)x"),
+            Expr::Cast(type_name("int", 0), var("x"))
+        );
+        assert_eq!(parse_expr("(x)"), *var("x"));
+        assert_eq!(
+            parse_expr("(x) - 1"),
+            *binary(BinaryOp::Sub, var("x"), int(1))
+        );
+        assert_eq!(
+            parse_expr("(unsigned long)-1"),
+            Expr::Cast(
+                type_name("unsigned long", 0),
+                Box::new(Expr::Unary(UnaryOp::Neg, int(1)))
+            )
+        );
+        assert_eq!(
+            parse_expr("(const struct node *)p"),
+            Expr::Cast(type_name("struct node", 1), var("p"))
+        );
+        // A typedef name only starts a cast if it names a type
+        assert_eq!(
+            parse_expr("(node_t **)p"),
+            Expr::Cast(type_name("node_t", 2), var("p"))
+        );
+        // A cast applies to the operand after it, not the whole expression
+        assert_eq!(
+            parse_expr("(char)a + b"),
+            *binary(
+                BinaryOp::Add,
+                Box::new(Expr::Cast(type_name("char", 0), var("a"))),
+                var("b")
+            )
+        );
+    }
+
+    #[test]
+    fn test_postfix_chains() {
+        let member = |expr, name: &str| Box::new(Expr::Member(expr, name.to_string()));
+        let index = |expr, i| Box::new(Expr::Index(expr, i));
+        assert_eq!(
+            parse_expr("list->next->value"),
+            *member(member(var("list"), "next"), "value")
+        );
+        assert_eq!(
+            parse_expr("a.b[1].c"),
+            *member(index(member(var("a"), "b"), int(1)), "c")
+        );
+        assert_eq!(
+            parse_expr("nodes[i + 1]->next"),
+            *member(
+                index(var("nodes"), binary(BinaryOp::Add, var("i"), int(1))),
+                "next"
+            )
+        );
+        // Postfix operators bind more tightly than prefix ones
+        assert_eq!(
+            parse_expr("*p->next"),
+            Expr::Unary(UnaryOp::Deref, member(var("p"), "next"))
+        );
+        assert_eq!(
+            parse_expr("&a[2]"),
+            Expr::Unary(UnaryOp::AddressOf, index(var("a"), int(2)))
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        let suffix = |unsigned, long| IntegerSuffix { unsigned, long };
+        assert_eq!(parse_expr("42"), *int(42));
+        assert_eq!(parse_expr("0x1f"), *int(31));
+        assert_eq!(parse_expr("1u"), Expr::Integer(1, suffix(true, false)));
+        assert_eq!(parse_expr("1UL"), Expr::Integer(1, suffix(true, true)));
+        assert_eq!(parse_expr("0xffl"), Expr::Integer(255, suffix(false, true)));
+        assert_eq!(parse_expr("1.5"), Expr::Float(1.5));
+        assert_eq!(parse_expr("'a'"), Expr::Char(b'a'));
+        assert_eq!(parse_expr("'\\n'"), Expr::Char(b'\n'));
+        assert_eq!(parse_expr("$rax"), Expr::Register("rax".to_string()));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let is_type = |_: &str| false;
+        assert!(parse("", &is_type).is_err());
+        assert!(parse("1 +", &is_type).is_err());
+        assert!(parse("(1", &is_type).is_err());
+        assert!(parse("a.", &is_type).is_err());
+        assert!(parse("a b", &is_type).is_err());
+        assert!(parse("12abc", &is_type).is_err());
+        assert!(parse("#", &is_type).is_err());
+    }
+}
@@ -10,13 +10,18 @@ use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    Encoding, Expression, File, Function, Line, Location, Member, Type, TypeKind, Variable,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         Ok(object
@@ -44,6 +49,15 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
 
     let mut compilation_units: Vec<File> = Vec::new();
 
+    // Collect every unit's types before anything else, so that variables can refer to types
+    // that are declared after them, and so that type names are worked out in one pass
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        load_types(&unit, &dwarf, &mut offset_to_type)?;
+    }
+    resolve_type_names(&mut offset_to_type);
+
     // Iterate over the compilation units.
     let mut iter = dwarf.units();
     while let Some(header) = iter.next()? {
@@ -51,9 +65,16 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
 
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // The depth of the inlined function we are in, if any. Inlined functions don't get frames
+        // of their own, so their variables would otherwise be mistaken for their caller's.
+        let mut inlined_depth = None;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            match inlined_depth {
+                Some(inlined_depth) if depth > inlined_depth => continue,
+                _ => inlined_depth = None,
+            }
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
@@ -74,37 +95,10 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
+                gimli::DW_TAG_inlined_subroutine => inlined_depth = Some(depth),
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
+                    for attr in get_attrs(entry, &unit)? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
@@ -129,6 +123,14 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = offset_to_type.get(&offset).cloned();
+                                }
+                            }
+                            gimli::DW_AT_frame_base => {
+                                func.frame_base = get_location(&attr, &unit, &dwarf);
+                            }
                             _ => {}
                         }
                     }
@@ -139,8 +141,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
+                    for attr in get_attrs(entry, &unit)? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
@@ -157,7 +158,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, &dwarf) {
                                     location = Some(loc);
                                 }
                             }
@@ -175,6 +176,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             entity_type: entity_type.unwrap(),
                             location: location.unwrap(),
                             line_number: line_number.try_into().unwrap(),
+                            is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
                         };
                         if depth == 1 {
                             compilation_units
@@ -202,12 +204,19 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
 
         // Get line numbers
         if let Some(program) = unit.line_program.clone() {
+            // Paths in the line program and unit names may be relative to the compilation
+            // directory, so compare them once they are resolved against it
+            let comp_dir = path::PathBuf::from(
+                unit.comp_dir
+                    .map(|dir| dir.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            );
             // Iterate over the line program rows.
             let mut rows = program.rows();
             while let Some((header, row)) = rows.next_row()? {
                 if !row.end_sequence() {
                     // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
+                    let mut path = comp_dir.clone();
                     if let Some(file) = row.file(header) {
                         if let Some(dir) = file.directory(header) {
                             path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
@@ -223,7 +232,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     // Get the File
                     let file = compilation_units
                         .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                        .find(|f| comp_dir.join(&f.name) == path);
 
                     // Determine line/column. DWARF line/column is never 0, so we use that
                     // but other applications may want to display this differently.
@@ -240,7 +249,281 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+/// Records every type declared in `unit` in `offset_to_type`, keyed by the type's offset in the
+/// .debug_info section (which is what DW_AT_type attributes refer to).
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<(), Error> {
+    // Offsets of the entries enclosing the current one, so that struct members and array
+    // subranges can find the type they belong to
+    let mut parents: Vec<usize> = Vec::new();
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        let offset = get_section_offset(entry.offset(), unit);
+        parents.truncate(depth as usize);
+        let parent = parents.last().cloned();
+        parents.push(offset);
+
+        let name = get_name(entry, unit, dwarf);
+        let size = entry
+            .attr(gimli::DW_AT_byte_size)?
+            .and_then(|attr| attr.udata_value())
+            .unwrap_or(0) as usize;
+        let target = get_type_offset(entry, unit)?;
+        match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let name = name.unwrap_or_else(|| "<unknown>".to_string());
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(encoding)) => match encoding {
+                        gimli::DW_ATE_unsigned => Encoding::Unsigned,
+                        gimli::DW_ATE_signed_char => Encoding::SignedChar,
+                        gimli::DW_ATE_unsigned_char => Encoding::UnsignedChar,
+                        gimli::DW_ATE_float => Encoding::Float,
+                        gimli::DW_ATE_boolean => Encoding::Boolean,
+                        _ => Encoding::Signed,
+                    },
+                    _ => Encoding::Signed,
+                };
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(name, size, TypeKind::Base(encoding)),
+                );
+            }
+            gimli::DW_TAG_pointer_type => {
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(String::new(), size, TypeKind::Pointer(target)),
+                );
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+                let keyword = if entry.tag() == gimli::DW_TAG_union_type {
+                    "union"
+                } else {
+                    "struct"
+                };
+                let name = format!("{} {}", keyword, name.unwrap_or_default());
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(name, size, TypeKind::Struct(vec![])),
+                );
+            }
+            gimli::DW_TAG_enumeration_type => {
+                let name = format!("enum {}", name.unwrap_or_default());
+                offset_to_type.insert(offset, Type::with_kind(name, size, TypeKind::Enum(vec![])));
+            }
+            gimli::DW_TAG_enumerator => {
+                let value = entry.attr(gimli::DW_AT_const_value)?.and_then(|attr| {
+                    attr.sdata_value()
+                        .or_else(|| attr.udata_value().map(|value| value as i64))
+                });
+                let parent_type = parent.and_then(|parent| offset_to_type.get_mut(&parent));
+                if let (
+                    Some(Type {
+                        kind: TypeKind::Enum(values),
+                        ..
+                    }),
+                    Some(value),
+                ) = (parent_type, value)
+                {
+                    values.push((name.unwrap_or_default(), value));
+                }
+            }
+            gimli::DW_TAG_typedef => {
+                let name = name.unwrap_or_default();
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(name, size, TypeKind::Typedef(target)),
+                );
+            }
+            gimli::DW_TAG_const_type => {
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(String::new(), size, TypeKind::Const(target)),
+                );
+            }
+            gimli::DW_TAG_volatile_type => {
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(String::new(), size, TypeKind::Volatile(target)),
+                );
+            }
+            gimli::DW_TAG_subroutine_type => {
+                offset_to_type.insert(
+                    offset,
+                    Type::with_kind(String::new(), size, TypeKind::Function(target)),
+                );
+            }
+            gimli::DW_TAG_member => {
+                let member_offset = entry
+                    .attr(gimli::DW_AT_data_member_location)?
+                    .and_then(|attr| attr.udata_value())
+                    .unwrap_or(0) as usize;
+                let parent_type = parent.and_then(|parent| offset_to_type.get_mut(&parent));
+                if let (
+                    Some(Type {
+                        kind: TypeKind::Struct(members),
+                        ..
+                    }),
+                    Some(target),
+                ) = (parent_type, target)
+                {
+                    members.push(Member {
+                        name: name.unwrap_or_default(),
+                        type_offset: target,
+                        offset: member_offset,
+                    });
+                }
+            }
+            gimli::DW_TAG_array_type => {
+                if let Some(target) = target {
+                    offset_to_type.insert(
+                        offset,
+                        Type::with_kind(String::new(), size, TypeKind::Array(target, 0)),
+                    );
+                }
+            }
+            gimli::DW_TAG_subrange_type => {
+                let count = if let Some(attr) = entry.attr(gimli::DW_AT_count)? {
+                    attr.udata_value().unwrap_or(0)
+                } else if let Some(attr) = entry.attr(gimli::DW_AT_upper_bound)? {
+                    attr.udata_value().map(|bound| bound + 1).unwrap_or(0)
+                } else {
+                    0
+                };
+                let parent_type = parent.and_then(|parent| offset_to_type.get_mut(&parent));
+                if let Some(Type {
+                    kind: TypeKind::Array(_, len),
+                    ..
+                }) = parent_type
+                {
+                    *len = count as usize;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Pointer, array and const/volatile types have no name of their own in DWARF, and typedefs and
+/// qualified types have no size. This fills in names (like `const char *` or `int [4]`) and sizes
+/// for them, derived from the types they refer to.
+fn resolve_type_names(offset_to_type: &mut HashMap<usize, Type>) {
+    fn resolve(
+        offset_to_type: &HashMap<usize, Type>,
+        offset: usize,
+        depth: usize,
+    ) -> (String, usize) {
+        let ty = match offset_to_type.get(&offset) {
+            Some(ty) => ty,
+            None => return ("<unknown>".to_string(), 0),
+        };
+        if (!ty.name.is_empty() && ty.size != 0) || depth > 16 {
+            return (ty.name.clone(), ty.size);
+        }
+        let resolve_target = |target: Option<usize>| match target {
+            Some(target) => resolve(offset_to_type, target, depth + 1),
+            None => ("void".to_string(), 0),
+        };
+        let (name, size) = match ty.kind {
+            TypeKind::Pointer(target) => {
+                let function = target.and_then(|target| offset_to_type.get(&target));
+                let name = if let Some(Type {
+                    kind: TypeKind::Function(ret),
+                    ..
+                }) = function
+                {
+                    format!("{} (*)()", resolve_target(*ret).0)
+                } else {
+                    let (target_name, _) = resolve_target(target);
+                    if target_name.ends_with('*') {
+                        format!("{}*", target_name)
+                    } else {
+                        format!("{} *", target_name)
+                    }
+                };
+                (name, ty.size)
+            }
+            TypeKind::Array(elem, len) => {
+                let (elem_name, elem_size) = resolve(offset_to_type, elem, depth + 1);
+                (format!("{} [{}]", elem_name, len), elem_size * len)
+            }
+            TypeKind::Typedef(target) => (ty.name.clone(), resolve_target(target).1),
+            TypeKind::Const(target) => {
+                let (target_name, size) = resolve_target(target);
+                (format!("const {}", target_name), size)
+            }
+            TypeKind::Volatile(target) => {
+                let (target_name, size) = resolve_target(target);
+                (format!("volatile {}", target_name), size)
+            }
+            TypeKind::Function(ret) => (format!("{} ()", resolve_target(ret).0), 0),
+            _ => (ty.name.clone(), ty.size),
+        };
+        let name = if ty.name.is_empty() {
+            name
+        } else {
+            ty.name.clone()
+        };
+        let size = if ty.size == 0 { size } else { ty.size };
+        (name, size)
+    }
+
+    let unnamed: Vec<usize> = offset_to_type
+        .iter()
+        .filter(|(_, ty)| ty.name.is_empty() || ty.size == 0)
+        .map(|(offset, _)| *offset)
+        .collect();
+    let resolved: Vec<(usize, String, usize)> = unnamed
+        .into_iter()
+        .map(|offset| {
+            let (name, size) = resolve(offset_to_type, offset, 0);
+            (offset, name, size)
+        })
+        .collect();
+    for (offset, name, size) in resolved {
+        let ty = offset_to_type.get_mut(&offset).unwrap();
+        ty.name = name;
+        ty.size = size;
+    }
+}
+
+fn get_section_offset<R: Reader>(offset: UnitOffset<usize>, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+fn get_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    let attr = entry.attr(gimli::DW_AT_name).ok()??;
+    match get_attr_value(&attr, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the .debug_info offset of the type referenced by an entry's DW_AT_type, if any.
+fn get_type_offset<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Option<usize>, Error> {
+    Ok(match entry.attr_value(gimli::DW_AT_type)? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => Some(get_section_offset(offset, unit)),
+        Some(gimli::AttributeValue::DebugInfoRef(offset)) => Some(offset.0),
+        _ => None,
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -291,25 +574,80 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
+/// Returns the attributes of an entry. Optimized code describes an inlined function (and each of
+/// its variables) once, in an abstract entry, which the function's out-of-line copy refers to with
+/// DW_AT_abstract_origin instead of repeating its name and type. The abstract entry's attributes
+/// come first, so that the concrete entry's own attributes override them.
+fn get_attrs<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Vec<gimli::Attribute<R>>, Error> {
+    let mut attrs = Vec::new();
+    if let Some(gimli::AttributeValue::UnitRef(offset)) =
+        entry.attr_value(gimli::DW_AT_abstract_origin)?
+    {
+        let origin = unit.entry(offset)?;
+        let mut origin_attrs = origin.attrs();
+        while let Some(attr) = origin_attrs.next()? {
+            attrs.push(attr);
+        }
+    }
+    let mut entry_attrs = entry.attrs();
+    while let Some(attr) = entry_attrs.next()? {
+        attrs.push(attr);
+    }
+    Ok(attrs)
+}
+
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let encoding = unit.encoding();
+    match attr.value() {
+        gimli::AttributeValue::Exprloc(ref data) => {
+            // The common cases of a lone DW_OP_fbreg or DW_OP_addr don't need an evaluator
+            let mut pc = data.0.clone();
             if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
+                if pc.is_empty() {
+                    match op {
+                        gimli::Operation::FrameOffset { offset } => {
+                            return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
+                        }
+                        gimli::Operation::Address { address } => {
+                            return Some(Location::Address(address.try_into().unwrap()));
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
+            Some(Location::Expression(get_expression(data, encoding)?))
+        }
+        gimli::AttributeValue::LocationListsRef(offset) => {
+            let mut entries = dwarf.locations(unit, offset).ok()?;
+            let mut list = Vec::new();
+            while let Ok(Some(entry)) = entries.next() {
+                list.push((
+                    entry.range.begin.try_into().unwrap(),
+                    entry.range.end.try_into().unwrap(),
+                    get_expression(&entry.data, encoding)?,
+                ));
+            }
+            Some(Location::List(list))
         }
+        _ => None,
     }
-    None
+}
+
+fn get_expression<R: Reader>(
+    expression: &gimli::Expression<R>,
+    encoding: gimli::Encoding,
+) -> Option<Expression> {
+    Some(Expression {
+        bytes: expression.0.to_slice().ok()?.into_owned(),
+        encoding,
+    })
 }
 
 // based on dwarf_dump.rs
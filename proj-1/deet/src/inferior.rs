@@ -3,12 +3,15 @@ use nix::sys::signal;
 use nix::sys::signal::Signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::collections::HashMap;
+use crate::coredump::CoreDump;
+use crate::signals::{describe, SignalTable};
+use libc::{user_fpregs_struct, user_regs_struct};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
 use std::process::Child;
 use std::process::Command;
 use std::os::unix::process::CommandExt;
 use std::mem::size_of;
-use crate::dwarf_data::DwarfData;
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -21,6 +24,25 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior forked. Contains the pid of the new process, which is stopped and
+    /// being traced, and still has our breakpoints in its copy of the inferior's memory.
+    Forked(Pid),
+
+    /// Indicates the inferior called exec, and is stopped at the start of the new program.
+    Execed,
+}
+
+/// What we want to hear about from the inferior besides signals. Vforks aren't traced: a vfork
+/// child shares its parent's memory, so it can't be detached from or held like a forked one.
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+}
+
+fn is_event(event: i32, expected: ptrace::Event) -> bool {
+    event == expected as i32
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -34,14 +56,211 @@ fn child_traceme() -> Result<(), std::io::Error> {
     )))
 }
 
+/// The registers shown by `info registers`, in the order gdb shows them.
+pub const REGISTER_NAMES: [&str; 26] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs", "fs_base", "gs_base",
+];
+
+/// Names of the eflags bits worth showing, by bit position.
+const EFLAGS: [(u64, &str); 9] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+];
+
+/// Formats eflags as the set flags, e.g. "[ ZF PF IF ]".
+pub fn format_eflags(eflags: u64) -> String {
+    let set: String = EFLAGS
+        .iter()
+        .filter(|(bit, _)| eflags & (1 << bit) != 0)
+        .map(|(_, name)| format!("{} ", name))
+        .collect();
+    format!("[ {}]", set)
+}
+
+/// Returns a mutable reference to a register given its name (without the `$`), e.g. "rax".
+pub fn register_mut<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        "orig_rax" => &mut regs.orig_rax,
+        _ => return None,
+    })
+}
+
+/// Returns the value of a register given its name (without the `$`).
+pub fn get_register(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    register_mut(&mut regs, name).map(|reg| *reg)
+}
+
+/// A thread of the inferior. Threads are numbered from 1 in the order we learn about them.
+#[derive(Debug, Clone, Copy)]
+pub struct Thread {
+    pub id: usize,
+    pub tid: Pid,
+}
+
+/// A region of the inferior's address space, from /proc/<pid>/maps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub start: usize,
+    /// One past the last address in the region.
+    pub end: usize,
+    /// Where in the file the region starts.
+    pub offset: usize,
+    /// E.g. "r-xp", or empty if not known.
+    pub perms: String,
+    /// The file mapped, a name like "[stack]" or "[heap]", or empty for anonymous memory.
+    pub path: String,
+}
+
+/// Parses the contents of /proc/<pid>/maps.
+pub fn parse_mappings(maps: &str) -> Vec<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            // start-end perms offset dev inode [path]
+            let fields: Vec<&str> = line.splitn(6, ' ').collect();
+            let mut range = fields.get(0)?.split('-');
+            Some(Mapping {
+                start: usize::from_str_radix(range.next()?, 16).ok()?,
+                end: usize::from_str_radix(range.next()?, 16).ok()?,
+                offset: usize::from_str_radix(fields.get(2)?, 16).ok()?,
+                perms: fields.get(1)?.to_string(),
+                path: fields
+                    .get(5)
+                    .map(|path| path.trim())
+                    .unwrap_or("")
+                    .to_string(),
+            })
+        })
+        .collect()
+}
+
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// The process we spawned, or None if we attached to it or it was forked by an inferior.
+    child: Option<Child>,
+    /// Whether we attached to a process that was already running (or one forked by it), which
+    /// should be left running when we are done with it rather than killed.
+    attached: bool,
+    /// Every live thread, including the main one. The inferior runs all of its threads or none:
+    /// when one stops, the others are stopped too.
+    threads: Vec<Thread>,
+    next_thread_id: usize,
+    /// The thread that registers are read from and that gets single-stepped: the one that
+    /// stopped last, unless another was selected.
+    current: Pid,
+    /// The thread whose stop was last reported. It may be sitting on a breakpoint, which it has
+    /// to step over when resumed; other threads there have yet to report hitting it.
+    stopped: Pid,
+    /// Threads that we sent a SIGSTOP which they have yet to report, because something else
+    /// stopped them first.
+    pending_sigstops: HashSet<Pid>,
+    /// Signals that other threads received while we were stopping them, to be delivered when
+    /// they resume.
+    pending_signals: HashMap<Pid, Signal>,
+    /// New threads and forked processes whose initial SIGSTOP came in before the event that
+    /// tells us what they are.
+    early_stops: HashSet<Pid>,
+    /// For a process that is gone, the core file its registers and memory are read from.
+    /// Nothing can be done to such an inferior other than looking at it.
+    core: Option<CoreDump>,
+}
+
+fn not_in_core() -> nix::Error {
+    nix::Error::Sys(nix::errno::Errno::EIO)
+}
+
+/// Returns the offset of debug register DR<index> in `struct user`, for PTRACE_PEEKUSER and
+/// PTRACE_POKEUSER.
+fn debug_register_offset(index: usize) -> usize {
+    let user: libc::user = unsafe { std::mem::zeroed() };
+    let base = &user as *const libc::user as usize;
+    let debugreg = &user.u_debugreg as *const _ as usize;
+    debugreg - base + index * size_of::<u64>()
 }
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+/// Reads debug register DR<index> of a thread (through the `u_debugreg` field of its `struct
+/// user`).
+fn get_debug_register(tid: Pid, index: usize) -> Result<u64, nix::Error> {
+    let value = unsafe {
+        nix::errno::Errno::clear();
+        libc::ptrace(
+            libc::PTRACE_PEEKUSER,
+            tid.as_raw(),
+            debug_register_offset(index),
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    // PEEKUSER returns the value read, so -1 is only an error if errno says so
+    if value == -1 && nix::errno::errno() != 0 {
+        return Err(nix::Error::Sys(nix::errno::Errno::last()));
+    }
+    Ok(value as u64)
+}
+
+fn set_debug_register(tid: Pid, index: usize, value: u64) -> Result<(), nix::Error> {
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_POKEUSER,
+            tid.as_raw(),
+            debug_register_offset(index),
+            value as usize,
+        )
+    };
+    nix::errno::Errno::result(res)?;
+    Ok(())
+}
+
+/// Sends a signal to one thread of a process.
+fn tgkill(pid: Pid, tid: Pid, signal: Signal) -> Result<(), nix::Error> {
+    let res = unsafe { libc::syscall(libc::SYS_tgkill, pid.as_raw(), tid.as_raw(), signal as i32) };
+    nix::errno::Errno::result(res)?;
+    Ok(())
+}
+
+/// Waits for a particular thread (or, with None, any thread) of the inferior. __WALL is needed
+/// to hear from threads other than the main one.
+fn wait_thread(tid: Option<Pid>) -> Result<WaitStatus, nix::Error> {
+    waitpid(tid, Some(WaitPidFlag::__WALL))
+}
+
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
@@ -59,7 +278,7 @@ impl Inferior {
             WaitStatus::Stopped(_pid, signal) => {
             // WaitStatus::Signaled(_pid, signal, _core_dumped) => {
                 if signal == Signal::SIGTRAP {
-                    return Some(Inferior {child});
+                    return Inferior::with_main_thread(child_id, Some(child), false).ok();
                 }
             },
             _ => return None,
@@ -71,81 +290,684 @@ impl Inferior {
         None
     }
 
+    /// Attaches to an already-running process, leaving it stopped.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        let mut attached = Vec::new();
+        let result = Inferior::attach_threads(pid, &mut attached);
+        // Threads left attached after a failure would stay stopped for good
+        if result.is_err() {
+            for tid in attached {
+                let _ = ptrace::detach(tid, None);
+            }
+        }
+        result
+    }
+
+    /// Does the work of `attach`, adding each thread to `attached` as soon as it is attached to.
+    fn attach_threads(pid: Pid, attached: &mut Vec<Pid>) -> Result<Inferior, nix::Error> {
+        // Each thread has to be attached to separately, starting with the main one
+        let mut inferior = None;
+        let mut tids = vec![pid];
+        'threads: while let Some(tid) = tids.pop() {
+            match ptrace::attach(tid) {
+                // A thread may exit between being listed and being attached to
+                Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) if tid != pid => continue,
+                result => result?,
+            }
+            attached.push(tid);
+            // PTRACE_ATTACH sends the thread a SIGSTOP; wait for it to take effect, passing on
+            // any signal that gets there first
+            loop {
+                match wait_thread(Some(tid))? {
+                    WaitStatus::Stopped(_, Signal::SIGSTOP) => break,
+                    WaitStatus::Stopped(_, signal) => ptrace::cont(tid, Some(signal))?,
+                    WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) if tid != pid => {
+                        attached.pop();
+                        continue 'threads;
+                    }
+                    _ => return Err(nix::Error::Sys(nix::errno::Errno::ESRCH)),
+                }
+            }
+            match inferior.as_mut() {
+                None => {
+                    inferior = Some(Inferior::with_main_thread(pid, None, true)?);
+                    let tasks = std::fs::read_dir(format!("/proc/{}/task", pid))
+                        .map_err(|_| nix::Error::Sys(nix::errno::Errno::ESRCH))?;
+                    tids = tasks
+                        .filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok())
+                        .map(Pid::from_raw)
+                        .filter(|tid| *tid != pid)
+                        .collect();
+                    // Popped in order of creation
+                    tids.sort_by_key(|tid| std::cmp::Reverse(tid.as_raw()));
+                }
+                Some(inferior) => inferior.add_thread(tid, pid)?,
+            }
+        }
+        Ok(inferior.unwrap())
+    }
+
+    /// Takes on a process forked by another inferior, which is stopped and already traced.
+    pub fn from_fork(pid: Pid, parent: &Inferior) -> Result<Inferior, nix::Error> {
+        Inferior::with_main_thread(pid, None, parent.attached)
+    }
+
+    /// Opens a core file left behind by a process that crashed, to be examined as if that
+    /// process were stopped at the moment it died.
+    pub fn from_core(path: &str) -> Result<Inferior, String> {
+        Ok(Inferior::with_core(CoreDump::open(path)?))
+    }
+
+    /// Sets up an inferior whose state all comes from a core dump.
+    pub fn with_core(core: CoreDump) -> Inferior {
+        let threads: Vec<Thread> = core
+            .threads
+            .iter()
+            .enumerate()
+            .map(|(index, thread)| Thread {
+                id: index + 1,
+                tid: thread.tid,
+            })
+            .collect();
+        // The thread that crashed is the first one
+        let crashed = threads[0].tid;
+        Inferior {
+            pid: core.pid,
+            child: None,
+            attached: false,
+            next_thread_id: threads.len() + 1,
+            threads,
+            current: crashed,
+            stopped: crashed,
+            pending_sigstops: HashSet::new(),
+            pending_signals: HashMap::new(),
+            early_stops: HashSet::new(),
+            core: Some(core),
+        }
+    }
+
+    /// Sets up an inferior whose main thread is stopped and being traced.
+    fn with_main_thread(
+        pid: Pid,
+        child: Option<Child>,
+        attached: bool,
+    ) -> Result<Inferior, nix::Error> {
+        // Have new threads and processes traced (and stopped) as soon as they are created
+        ptrace::setoptions(pid, trace_options())?;
+        Ok(Inferior {
+            pid,
+            child,
+            attached,
+            threads: vec![Thread { id: 1, tid: pid }],
+            next_thread_id: 2,
+            current: pid,
+            stopped: pid,
+            pending_sigstops: HashSet::new(),
+            pending_signals: HashMap::new(),
+            early_stops: HashSet::new(),
+            core: None,
+        })
+    }
+
+    /// Starts tracking a new thread, which must be stopped. New threads don't inherit debug
+    /// registers, so the watchpoints are copied over from `parent`, which must be stopped too
+    /// (the current thread may well be running when another one creates a thread).
+    fn add_thread(&mut self, tid: Pid, parent: Pid) -> Result<(), nix::Error> {
+        ptrace::setoptions(tid, trace_options())?;
+        let thread = Thread {
+            id: self.next_thread_id,
+            tid,
+        };
+        self.next_thread_id += 1;
+        self.threads.push(thread);
+        for index in &[0, 1, 2, 3, 7] {
+            let value = get_debug_register(parent, *index)?;
+            set_debug_register(tid, *index, value)?;
+        }
+        println!("[New thread {} (LWP {})]", thread.id, tid);
+        Ok(())
+    }
+
+    fn remove_thread(&mut self, tid: Pid) {
+        if let Some(index) = self.threads.iter().position(|thread| thread.tid == tid) {
+            let thread = self.threads.remove(index);
+            println!("[Thread {} (LWP {}) exited]", thread.id, tid);
+        }
+        self.pending_sigstops.remove(&tid);
+        self.pending_signals.remove(&tid);
+        if self.current == tid {
+            self.current = self.pid;
+        }
+        if self.stopped == tid {
+            self.stopped = self.pid;
+        }
+    }
+
+    fn has_thread(&self, tid: Pid) -> bool {
+        self.threads.iter().any(|thread| thread.tid == tid)
+    }
+
+    /// Returns the live threads, in the order they were created.
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// Returns the thread that is stopped on, or that was selected with `select_thread`.
+    pub fn current_thread(&self) -> Thread {
+        *self
+            .threads
+            .iter()
+            .find(|thread| thread.tid == self.current)
+            .unwrap_or(&self.threads[0])
+    }
+
+    /// Makes the thread with the given id the current one. Returns false if there is no such
+    /// thread.
+    pub fn select_thread(&mut self, id: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.id == id) {
+            Some(thread) => {
+                self.current = thread.tid;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads the registers of any thread, not just the current one.
+    pub fn get_thread_registers(&self, tid: Pid) -> Result<user_regs_struct, nix::Error> {
+        match &self.core {
+            Some(core) => match core.thread(tid) {
+                Some(thread) => Ok(thread.regs),
+                None => Err(nix::Error::Sys(nix::errno::Errno::ESRCH)),
+            },
+            None => ptrace::getregs(tid),
+        }
+    }
+
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Returns the core file this inferior is read from, if it is not a live process.
+    pub fn core(&self) -> Option<&CoreDump> {
+        self.core.as_ref()
     }
 
-    pub fn cont(&mut self, breakpoints: &HashMap<usize, u8>) -> Result<Status, nix::Error> {
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        if let Some(orig_byte) = breakpoints.get(&(rip - 1)) {
-            println!("stopped at a breakpoint");
-            self.write_byte(rip - 1, *orig_byte).unwrap();
-            regs.rip = (rip - 1) as u64;
-            ptrace::setregs(self.pid(), regs).unwrap();
-            ptrace::step(self.pid(), None).unwrap();
-            match self.wait(None).unwrap() {
-                Status::Stopped(_, _) => {
-                    self.write_byte(rip - 1, 0xcc).unwrap();
-                },
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)),
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
+    /// Returns whether we attached to this inferior rather than spawning it.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    pub fn get_registers(&self) -> Result<user_regs_struct, nix::Error> {
+        self.get_thread_registers(self.current)
+    }
+
+    pub fn set_registers(&self, regs: user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.current, regs)
+    }
+
+    /// Reads the inferior's memory map, in order of address.
+    pub fn mappings(&self) -> std::io::Result<Vec<Mapping>> {
+        if let Some(core) = &self.core {
+            return Ok(core.mappings.clone());
+        }
+        let maps = std::fs::read_to_string(format!("/proc/{}/maps", self.pid))?;
+        Ok(parse_mappings(&maps))
+    }
+
+    /// Returns the address that the current thread was accessing when it got the fault signal
+    /// (SIGSEGV, SIGBUS, SIGILL or SIGFPE) it is stopped with.
+    pub fn get_fault_address(&self) -> Result<usize, nix::Error> {
+        if let Some(core) = &self.core {
+            return core.fault_address.ok_or_else(not_in_core);
+        }
+        let info = ptrace::getsiginfo(self.current)?;
+        // libc doesn't expose si_addr for glibc; it comes right after si_signo, si_errno and
+        // si_code, aligned to 8 bytes
+        let base = &info as *const libc::siginfo_t as *const u8;
+        Ok(unsafe { *(base.add(16) as *const usize) })
+    }
+
+    pub fn get_fp_registers(&self) -> Result<user_fpregs_struct, nix::Error> {
+        if let Some(core) = &self.core {
+            let thread = core.thread(self.current);
+            return thread
+                .and_then(|thread| thread.fpregs)
+                .ok_or_else(not_in_core);
+        }
+        let mut fpregs: user_fpregs_struct = unsafe { std::mem::zeroed() };
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                self.current.as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut fpregs as *mut user_fpregs_struct,
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(fpregs)
+    }
+
+    /// Reads debug register DR<index> of the current thread.
+    pub fn get_debug_register(&self, index: usize) -> Result<u64, nix::Error> {
+        get_debug_register(self.current, index)
+    }
+
+    /// Sets debug register DR<index> in every thread, since watchpoints apply to all of them.
+    pub fn set_debug_register(&self, index: usize, value: u64) -> Result<(), nix::Error> {
+        for thread in &self.threads {
+            set_debug_register(thread.tid, index, value)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the word at `addr` in the inferior's memory.
+    pub fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        if self.core.is_some() {
+            let bytes = self.read_bytes(addr, size_of::<usize>())?;
+            return Ok(usize::from_le_bytes(bytes.as_slice().try_into().unwrap()));
+        }
+        Ok(ptrace::read(self.current, addr as ptrace::AddressType)? as usize)
+    }
+
+    /// If the current thread is sitting on one of our breakpoints, temporarily restores the
+    /// original byte and executes that one instruction (with the other threads stopped, so that
+    /// they can't run past the breakpoint meanwhile). Returns the status after the step, or None
+    /// if there was no breakpoint to step over.
+    fn step_over_breakpoint(
+        &mut self,
+        breakpoints: &HashMap<usize, u8>,
+        signals: &SignalTable,
+    ) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(self.current)?.rip as usize;
+        if let Some(orig_byte) = breakpoints.get(&rip) {
+            self.write_byte(rip, *orig_byte)?;
+            ptrace::step(self.current, self.pending_signals.remove(&self.current))?;
+            let status = self.wait(breakpoints, signals, false)?;
+            if let Status::Stopped(_, _) = status {
+                self.write_byte(rip, 0xcc)?;
+            }
+            return Ok(Some(status));
+        }
+        Ok(None)
+    }
+
+    /// Resumes the inferior until it hits a breakpoint, receives a signal that `signals` says to
+    /// stop for, or exits. When it stops at a breakpoint, rip is rewound to the breakpoint's
+    /// address.
+    pub fn cont(
+        &mut self,
+        breakpoints: &HashMap<usize, u8>,
+        signals: &SignalTable,
+    ) -> Result<Status, nix::Error> {
+        let selected = self.current;
+        self.current = self.stopped;
+        match self.step_over_breakpoint(breakpoints, signals)? {
+            None => {}
+            // The instruction stepped over may have triggered a watchpoint, which the low four
+            // bits of DR6 say
+            Some(status @ Status::Stopped(Signal::SIGTRAP, _)) => {
+                if self.get_debug_register(6)? & 0xf != 0 {
+                    return Ok(status);
+                }
+            }
+            Some(status) => return Ok(status),
+        }
+        self.current = selected;
+        for thread in &self.threads {
+            ptrace::cont(thread.tid, self.pending_signals.remove(&thread.tid))?;
+        }
+        let status = self.wait(breakpoints, signals, true)?;
+        if let Status::Stopped(Signal::SIGTRAP, rip) = status {
+            let bp_addr = rip.wrapping_sub(1);
+            if breakpoints.contains_key(&bp_addr) {
+                let mut regs = ptrace::getregs(self.current)?;
+                regs.rip = bp_addr as u64;
+                ptrace::setregs(self.current, regs)?;
+                return Ok(Status::Stopped(Signal::SIGTRAP, bp_addr));
             }
         }
-        ptrace::cont(self.pid(), None)?;
-        self.wait(None)
+        Ok(status)
+    }
+
+    /// Executes a single instruction in the current thread.
+    pub fn step(
+        &mut self,
+        breakpoints: &HashMap<usize, u8>,
+        signals: &SignalTable,
+    ) -> Result<Status, nix::Error> {
+        if let Some(status) = self.step_over_breakpoint(breakpoints, signals)? {
+            return Ok(status);
+        }
+        ptrace::step(self.current, self.pending_signals.remove(&self.current))?;
+        self.wait(breakpoints, signals, false)
     }
 
     pub fn kill(&mut self) {
         println!("Killing running inferior (pid {})", self.pid());
-        self.child.kill().unwrap();
-        waitpid(self.pid(), None).unwrap();
+        // The inferior may have died already (say, an attached process that was killed from
+        // elsewhere), leaving nothing to kill or reap
+        match self.child.as_mut() {
+            Some(child) => {
+                let _ = child.kill();
+            }
+            None => match signal::kill(self.pid(), Signal::SIGKILL) {
+                Ok(()) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => {}
+                Err(err) => println!("Error killing inferior: {}", err),
+            },
+        }
+        // The main thread is only reaped once all the others are
+        for thread in &self.threads {
+            if thread.tid != self.pid {
+                let _ = wait_thread(Some(thread.tid));
+            }
+        }
+        let _ = wait_thread(Some(self.pid()));
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let user_regs = ptrace::getregs(self.pid())?;
-        let (mut rip, mut rbp) = (user_regs.rip, user_regs.rbp);
-        loop {
-            let line = debug_data.get_line_from_addr(rip as usize).unwrap();
-            let func = debug_data.get_function_from_addr(rip as usize).unwrap();
-            println!("{} ({})", line, func);
-            if func == "main".to_string() {
-                break;
+    /// Removes every inserted breakpoint and watchpoint, then lets the inferior run on its own.
+    pub fn detach(mut self, breakpoints: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        for (addr, orig_byte) in breakpoints {
+            self.write_byte(*addr, *orig_byte)?;
+        }
+        self.set_debug_register(7, 0)?;
+        self.reap_pending_sigstops()?;
+        for thread in &self.threads {
+            ptrace::detach(thread.tid, self.pending_signals.remove(&thread.tid))?;
+        }
+        Ok(())
+    }
+
+    /// Lets each thread that stop_other_threads sent a SIGSTOP it has yet to report run until it
+    /// does, so that the SIGSTOP doesn't stop the whole process once we have detached from it.
+    fn reap_pending_sigstops(&mut self) -> Result<(), nix::Error> {
+        let tids: Vec<Pid> = self.pending_sigstops.drain().collect();
+        for tid in tids {
+            let mut signal = self.pending_signals.remove(&tid);
+            loop {
+                ptrace::cont(tid, signal.take())?;
+                match wait_thread(Some(tid))? {
+                    WaitStatus::Stopped(_, Signal::SIGSTOP) => break,
+                    // Signals that arrive in the meantime are the program's
+                    WaitStatus::Stopped(_, other) => signal = Some(other),
+                    WaitStatus::PtraceEvent(_, _, event)
+                        if is_event(event, ptrace::Event::PTRACE_EVENT_CLONE) =>
+                    {
+                        let new_tid = self.wait_for_new_task(tid)?;
+                        self.add_thread(new_tid, tid)?;
+                    }
+                    WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) => {
+                        self.remove_thread(tid);
+                        break;
+                    }
+                    _ => {}
+                }
             }
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as u64;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as u64;
         }
-        // println!("%rip register: {:#x}", user_regs.rip);
         Ok(())
     }
 
     pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         let aligned_addr = align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
+        let word = ptrace::read(self.current, aligned_addr as ptrace::AddressType)? as u64;
         let orig_byte = (word >> 8 * byte_offset) & 0xff;
         let masked_word = word & !(0xff << 8 * byte_offset);
         let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
         ptrace::write(
-            self.pid(),
+            self.current,
             aligned_addr as ptrace::AddressType,
             updated_word as *mut std::ffi::c_void,
         )?;
         Ok(orig_byte as u8)
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+    /// Writes `bytes` to the inferior's memory starting at `addr`, a word at a time. Words that
+    /// are only partly overwritten are read first so that the bytes around them are preserved.
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < addr + bytes.len() {
+            let end = addr + bytes.len();
+            let whole_word = word_addr >= addr && word_addr + size_of::<usize>() <= end;
+            let mut word = if whole_word {
+                [0; 8]
+            } else {
+                let word = ptrace::read(self.current, word_addr as ptrace::AddressType)?;
+                (word as u64).to_le_bytes()
+            };
+            for (i, byte) in word.iter_mut().enumerate() {
+                let byte_addr = word_addr + i;
+                if byte_addr >= addr && byte_addr < end {
+                    *byte = bytes[byte_addr - addr];
+                }
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+            ptrace::write(
+                self.current,
+                word_addr as ptrace::AddressType,
+                u64::from_le_bytes(word) as *mut std::ffi::c_void,
+            )?;
+            word_addr += size_of::<usize>();
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes of the inferior's memory starting at `addr`.
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        if let Some(core) = &self.core {
+            return core.read_bytes(addr, len).ok_or_else(not_in_core);
+        }
+        let mut bytes = Vec::with_capacity(len);
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < addr + len {
+            let word = ptrace::read(self.current, word_addr as ptrace::AddressType)? as u64;
+            for (i, byte) in word.to_le_bytes().iter().enumerate() {
+                let byte_addr = word_addr + i;
+                if byte_addr >= addr && byte_addr < addr + len {
+                    bytes.push(*byte);
+                }
+            }
+            word_addr += size_of::<usize>();
+        }
+        Ok(bytes)
+    }
+
+    /// Waits for a thread of the inferior to stop (or for the inferior to exit), and returns a
+    /// Status to indicate the state of the process then. The thread that stopped becomes the
+    /// current one; if `stop_others` is set, the other threads (which must be running) are
+    /// stopped too. Threads being created or exiting meanwhile are kept track of, and signals
+    /// that `signals` says not to stop for are dealt with here.
+    fn wait(
+        &mut self,
+        breakpoints: &HashMap<usize, u8>,
+        signals: &SignalTable,
+        stop_others: bool,
+    ) -> Result<Status, nix::Error> {
+        // While single-stepping, the thread must carry on stepping after other events
+        let resume = if stop_others {
+            ptrace::cont
+        } else {
+            ptrace::step
+        };
+        loop {
+            match wait_thread(None)? {
+                WaitStatus::Exited(tid, exit_code) if tid == self.pid => {
+                    return Ok(Status::Exited(exit_code));
+                }
+                WaitStatus::Signaled(tid, signal, _core_dumped) if tid == self.pid => {
+                    return Ok(Status::Signaled(signal));
+                }
+                WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, _, _) => {
+                    self.remove_thread(tid);
+                }
+                WaitStatus::PtraceEvent(tid, _, event)
+                    if is_event(event, ptrace::Event::PTRACE_EVENT_CLONE) =>
+                {
+                    let new_tid = self.wait_for_new_task(tid)?;
+                    self.add_thread(new_tid, tid)?;
+                    if stop_others {
+                        ptrace::cont(new_tid, None)?;
+                    }
+                    resume(tid, None)?;
+                }
+                WaitStatus::PtraceEvent(tid, _, event)
+                    if is_event(event, ptrace::Event::PTRACE_EVENT_FORK) =>
+                {
+                    let child = self.wait_for_new_task(tid)?;
+                    self.current = tid;
+                    self.stopped = tid;
+                    if stop_others {
+                        self.stop_other_threads(breakpoints, signals)?;
+                    }
+                    return Ok(Status::Forked(child));
+                }
+                WaitStatus::PtraceEvent(_, _, event)
+                    if is_event(event, ptrace::Event::PTRACE_EVENT_EXEC) =>
+                {
+                    // Exec kills every other thread, and the thread that called it takes over
+                    // the main thread's id
+                    self.threads.truncate(1);
+                    self.current = self.pid;
+                    self.stopped = self.pid;
+                    self.pending_sigstops.clear();
+                    self.pending_signals.clear();
+                    return Ok(Status::Execed);
+                }
+                WaitStatus::Stopped(tid, Signal::SIGSTOP) if !self.has_thread(tid) => {
+                    self.early_stops.insert(tid);
+                }
+                WaitStatus::Stopped(tid, Signal::SIGSTOP) if self.pending_sigstops.remove(&tid) => {
+                    ptrace::cont(tid, self.pending_signals.remove(&tid))?;
+                }
+                WaitStatus::Stopped(tid, signal) if !signals.get(signal).stop => {
+                    let policy = signals.get(signal);
+                    if policy.print {
+                        println!("Child received signal {}, {}.", signal, describe(signal));
+                    }
+                    resume(tid, if policy.pass { Some(signal) } else { None })?;
+                }
+                WaitStatus::Stopped(tid, signal) => {
+                    if signals.get(signal).pass {
+                        self.pending_signals.insert(tid, signal);
+                    }
+                    if tid != self.current {
+                        self.current = tid;
+                        let thread = self.current_thread();
+                        println!("[Switching to thread {} (LWP {})]", thread.id, tid);
+                    }
+                    self.stopped = tid;
+                    if stop_others {
+                        self.stop_other_threads(breakpoints, signals)?;
+                    }
+                    let regs = ptrace::getregs(tid)?;
+                    return Ok(Status::Stopped(signal, regs.rip as usize));
+                }
+                WaitStatus::PtraceEvent(tid, _, _) | WaitStatus::PtraceSyscall(tid) => {
+                    resume(tid, None)?;
+                }
+                WaitStatus::Continued(_) | WaitStatus::StillAlive => {}
+            }
+        }
+    }
+
+    /// Returns the id of the thread or process that `tid` just created, once it has stopped
+    /// with the SIGSTOP that new tasks start with.
+    fn wait_for_new_task(&mut self, tid: Pid) -> Result<Pid, nix::Error> {
+        let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+        if !self.early_stops.remove(&new_tid) {
+            wait_thread(Some(new_tid))?;
+        }
+        Ok(new_tid)
+    }
+
+    /// Stops every thread other than the current one. A thread might stop for some other reason
+    /// before our SIGSTOP gets to it; a thread that hit a breakpoint is wound back to hit it again
+    /// when it resumes, and any other signal is delivered then if `signals` says to pass it.
+    fn stop_other_threads(
+        &mut self,
+        breakpoints: &HashMap<usize, u8>,
+        signals: &SignalTable,
+    ) -> Result<(), nix::Error> {
+        let others: Vec<Pid> = self
+            .threads
+            .iter()
+            .map(|thread| thread.tid)
+            .filter(|tid| *tid != self.current)
+            .collect();
+        for tid in others {
+            // A thread that is already gone will be reaped later
+            if tgkill(self.pid, tid, Signal::SIGSTOP).is_err() {
+                self.remove_thread(tid);
+                continue;
+            }
+            match wait_thread(Some(tid))? {
+                WaitStatus::Stopped(_, Signal::SIGSTOP) => {}
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                    let mut regs = ptrace::getregs(tid)?;
+                    if breakpoints.contains_key(&(regs.rip as usize).wrapping_sub(1)) {
+                        regs.rip -= 1;
+                        ptrace::setregs(tid, regs)?;
+                    }
+                    self.pending_sigstops.insert(tid);
+                }
+                WaitStatus::Stopped(_, signal) => {
+                    if signals.get(signal).pass {
+                        self.pending_signals.insert(tid, signal);
+                    }
+                    self.pending_sigstops.insert(tid);
+                }
+                WaitStatus::PtraceEvent(_, _, event)
+                    if is_event(event, ptrace::Event::PTRACE_EVENT_CLONE) =>
+                {
+                    let new_tid = self.wait_for_new_task(tid)?;
+                    self.add_thread(new_tid, tid)?;
+                    self.pending_sigstops.insert(tid);
+                }
+                WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) => self.remove_thread(tid),
+                _ => {
+                    self.pending_sigstops.insert(tid);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_eflags() {
+        // Bit 1 is always set, and isn't shown
+        assert_eq!(format_eflags(0x246), "[ PF ZF IF ]");
+        assert_eq!(format_eflags(0x2), "[ ]");
+        assert_eq!(format_eflags(0xfd5), "[ CF PF AF ZF SF TF IF DF OF ]");
+        // Neither are flags above OF, such as RF
+        assert_eq!(format_eflags(0x10283), "[ CF SF IF ]");
+    }
+
+    #[test]
+    fn test_parse_mappings() {
+        let mappings = parse_mappings(
+            "00401000-00402000 r-xp 00001000 08:01 1234                               /tmp/a b\n\
+             7f1c2d000000-7f1c2d021000 rw-p 00000000 00:00 0 \n\
+             7ffd12340000-7ffd12361000 rw-p 00000000 00:00 0                          [stack]\n\
+             garbage\n",
+        );
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(
+            mappings[0],
+            Mapping {
+                start: 0x401000,
+                end: 0x402000,
+                offset: 0x1000,
+                perms: "r-xp".to_string(),
+                path: "/tmp/a b".to_string(),
+            }
+        );
+        assert_eq!(mappings[1].start, 0x7f1c2d000000);
+        assert_eq!(mappings[1].path, "");
+        assert_eq!(mappings[2].path, "[stack]");
     }
 }
@@ -0,0 +1,336 @@
+//! Evaluation of DWARF location descriptions, which say where a variable's value lives in a given
+//! frame: at a fixed address, somewhere in the stack frame, in a register, or (in optimized code)
+//! split into pieces spread across several of these.
+
+use crate::dwarf_data::{DwarfData, Expression, Location, Variable};
+use crate::inferior::{self, Inferior};
+use crate::unwind::Frame;
+use crate::value;
+use gimli::{EndianSlice, EvaluationResult, LittleEndian, Register, Value};
+use std::mem::size_of;
+
+const OPTIMIZED_OUT: &str = "<optimized out>";
+
+/// DWARF register numbers 0-16 for x86-64 (16 is the return address, i.e. rip).
+const REGISTERS: [&str; 17] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+
+/// The first of xmm0-xmm15 in the DWARF register numbering.
+const XMM0: u16 = 17;
+
+/// Where a variable's value can be found.
+pub enum Place {
+    /// In the inferior's memory, at this address.
+    Memory(usize),
+    /// Not in memory at all (e.g. in a register, or a constant); this is the value itself.
+    Value(Vec<u8>),
+}
+
+/// Works out where a variable lives in the given frame.
+pub fn locate(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    var: &Variable,
+    frame: &Frame,
+) -> Result<Place, String> {
+    let size = var.entity_type.size;
+    resolve(debug_data, inferior, &var.location, frame, size, false)
+}
+
+/// Works out where a value of `size` bytes described by `location` lives in the given frame.
+/// `in_frame_base` is set while working out the frame base itself, which therefore can't be used.
+fn resolve(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    location: &Location,
+    frame: &Frame,
+    size: usize,
+    in_frame_base: bool,
+) -> Result<Place, String> {
+    let expression = match location {
+        Location::Address(addr) => return Ok(Place::Memory(*addr)),
+        Location::FramePointerOffset(offset) => {
+            let base = frame_base(debug_data, inferior, frame, in_frame_base)?;
+            return Ok(Place::Memory((base as isize + offset) as usize));
+        }
+        Location::Expression(expression) => expression,
+        Location::List(entries) => {
+            let pc = frame.lookup_pc();
+            match entries
+                .iter()
+                .find(|(start, end, _)| *start <= pc && pc < *end)
+            {
+                Some((_, _, expression)) => expression,
+                None => return Err(OPTIMIZED_OUT.to_string()),
+            }
+        }
+    };
+    evaluate(debug_data, inferior, expression, frame, size, in_frame_base)
+}
+
+/// Returns the frame base (DW_AT_frame_base) of the function the frame is in, which
+/// DW_OP_fbreg offsets are relative to. gcc makes it the canonical frame address, but it may
+/// just as well be a register such as rbp, or an offset from one. Functions that don't say are
+/// taken to use the CFA.
+fn frame_base(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    frame: &Frame,
+    in_frame_base: bool,
+) -> Result<usize, String> {
+    // A frame base defined in terms of itself could never be worked out
+    if in_frame_base {
+        return Err("<error evaluating location: frame base refers to itself>".to_string());
+    }
+    let location = match debug_data
+        .get_function_containing(frame.lookup_pc())
+        .and_then(|func| func.frame_base.as_ref())
+    {
+        Some(location) => location,
+        None => return Ok(frame.base()),
+    };
+    let size = size_of::<usize>();
+    // A register location (DW_OP_reg6) means the frame base is the register's value
+    match resolve(debug_data, inferior, location, frame, size, true)? {
+        Place::Memory(addr) => Ok(addr),
+        Place::Value(bytes) => Ok(value::bytes_to_u64(&bytes) as usize),
+    }
+}
+
+/// Returns the address of a variable, for variables that live in memory.
+pub fn variable_address(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    var: &Variable,
+    frame: &Frame,
+) -> Result<usize, String> {
+    match locate(debug_data, inferior, var, frame)? {
+        Place::Memory(addr) => Ok(addr),
+        Place::Value(_) => Err(format!(
+            "Address requested for identifier \"{}\" which is in a register",
+            var.name
+        )),
+    }
+}
+
+/// Reads the value of a variable in the given frame.
+pub fn read_variable(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    var: &Variable,
+    frame: &Frame,
+) -> Result<Vec<u8>, String> {
+    match locate(debug_data, inferior, var, frame)? {
+        Place::Memory(addr) => inferior
+            .read_bytes(addr, var.entity_type.size)
+            .map_err(|_| format!("<error: Cannot access memory at address {:#x}>", addr)),
+        Place::Value(bytes) => Ok(bytes),
+    }
+}
+
+/// Reads a register as it is in the given frame. Unwinding only recovers the callee-saved
+/// registers of outer frames, so anything else counts as optimized out there.
+fn read_register(
+    inferior: &Inferior,
+    frame: &Frame,
+    register: Register,
+) -> Result<Vec<u8>, String> {
+    match register.0 {
+        number if (number as usize) < REGISTERS.len() => {
+            let name = REGISTERS[number as usize];
+            if !frame.has_register(name) {
+                return Err(OPTIMIZED_OUT.to_string());
+            }
+            let value = inferior::get_register(&frame.regs, name).unwrap();
+            Ok(value.to_le_bytes().to_vec())
+        }
+        number if number >= XMM0 && number < XMM0 + 16 && frame.level == 0 => {
+            let fpregs = inferior.get_fp_registers().map_err(|err| err.to_string())?;
+            let index = (number - XMM0) as usize * 4;
+            Ok(fpregs.xmm_space[index..index + 4]
+                .iter()
+                .flat_map(|word| word.to_le_bytes().to_vec())
+                .collect())
+        }
+        _ => Err(OPTIMIZED_OUT.to_string()),
+    }
+}
+
+fn value_bytes(value: Value) -> Vec<u8> {
+    match value {
+        Value::F32(value) => value.to_le_bytes().to_vec(),
+        Value::F64(value) => value.to_le_bytes().to_vec(),
+        value => value.to_u64(!0).unwrap_or(0).to_le_bytes().to_vec(),
+    }
+}
+
+/// Runs a DWARF expression, supplying the registers, memory and frame base it asks for, and puts
+/// together the `size` bytes of the value from the pieces it describes.
+fn evaluate(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    expression: &Expression,
+    frame: &Frame,
+    size: usize,
+    in_frame_base: bool,
+) -> Result<Place, String> {
+    let bytes = EndianSlice::new(&expression.bytes, LittleEndian);
+    let mut evaluation = gimli::Expression(bytes).evaluation(expression.encoding);
+    let eval_error = |err: gimli::Error| format!("<error evaluating location: {}>", err);
+    let mut result = evaluation.evaluate().map_err(eval_error)?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let bytes = inferior
+                    .read_bytes(address as usize, size as usize)
+                    .map_err(|_| {
+                        format!("<error: Cannot access memory at address {:#x}>", address)
+                    })?;
+                evaluation.resume_with_memory(Value::Generic(value::bytes_to_u64(&bytes)))
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let bytes = read_register(inferior, frame, register)?;
+                evaluation.resume_with_register(Value::Generic(value::bytes_to_u64(&bytes)))
+            }
+            EvaluationResult::RequiresFrameBase => {
+                let base = frame_base(debug_data, inferior, frame, in_frame_base)?;
+                evaluation.resume_with_frame_base(base as u64)
+            }
+            EvaluationResult::RequiresCallFrameCfa => {
+                evaluation.resume_with_call_frame_cfa(frame.base() as u64)
+            }
+            // Only position-independent code is relocated, and that isn't supported anyway
+            EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(address)
+            }
+            // Entry values (the value a parameter had on entry to the function) would need the
+            // caller's registers at the time of the call, which are long gone
+            _ => return Err(OPTIMIZED_OUT.to_string()),
+        }
+        .map_err(eval_error)?;
+    }
+
+    let pieces = evaluation.result();
+    if pieces.len() == 1 && pieces[0].size_in_bits.is_none() {
+        if let gimli::Location::Address { address } = pieces[0].location {
+            return Ok(Place::Memory(address as usize));
+        }
+    }
+    if pieces.is_empty() {
+        return Err(OPTIMIZED_OUT.to_string());
+    }
+    let mut value = Vec::new();
+    for piece in pieces {
+        let piece_size = piece
+            .size_in_bits
+            .map_or(size, |bits| (bits as usize + 7) / 8);
+        let mut bytes = match piece.location {
+            gimli::Location::Address { address } => inferior
+                .read_bytes(address as usize, piece_size)
+                .map_err(|_| format!("<error: Cannot access memory at address {:#x}>", address))?,
+            gimli::Location::Register { register } => read_register(inferior, frame, register)?,
+            gimli::Location::Value { value } => value_bytes(value),
+            gimli::Location::Bytes { value } => value.to_vec(),
+            gimli::Location::Empty | gimli::Location::ImplicitPointer { .. } => {
+                return Err(OPTIMIZED_OUT.to_string());
+            }
+        };
+        bytes.resize(piece_size, 0);
+        value.extend(bytes);
+    }
+    value.resize(size, 0);
+    Ok(Place::Value(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coredump::CoreDump;
+    use crate::dwarf_data::{Encoding, File, Function, Type, TypeKind};
+    use std::collections::HashMap;
+
+    const CFA: usize = 0x7000;
+    const RBP: u64 = 0x6000;
+
+    fn expression(bytes: &[u8]) -> Location {
+        Location::Expression(Expression {
+            bytes: bytes.to_vec(),
+            encoding: gimli::Encoding {
+                format: gimli::Format::Dwarf32,
+                version: 4,
+                address_size: 8,
+            },
+        })
+    }
+
+    /// Returns the address of a local `int` with the given location, in a function with the
+    /// given frame base whose frame has the CFA and rbp above.
+    fn local_address(frame_base: Option<Location>, location: Location) -> Result<usize, String> {
+        let var = Variable {
+            name: "local".to_string(),
+            entity_type: Type::with_kind("int".to_string(), 4, TypeKind::Base(Encoding::Signed)),
+            location,
+            line_number: 2,
+            is_parameter: false,
+        };
+        let func = Function {
+            name: "func".to_string(),
+            address: 0x401000,
+            text_length: 0x100,
+            line_number: 1,
+            frame_base,
+            ..Default::default()
+        };
+        let file = File {
+            name: "func.c".to_string(),
+            functions: vec![func],
+            ..Default::default()
+        };
+        let debug_data = DwarfData::from_parts(vec![file], HashMap::new());
+        let inferior = Inferior::with_core(CoreDump::from_regions(&[(0x1000, &[0; 8])]));
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401010;
+        regs.rbp = RBP;
+        let frame = Frame {
+            level: 0,
+            regs,
+            cfa: Some(CFA),
+        };
+        variable_address(&debug_data, &inferior, &var, &frame)
+    }
+
+    #[test]
+    fn test_frame_base() {
+        let offset = Location::FramePointerOffset(-20);
+        // No DW_AT_frame_base, or DW_OP_call_frame_cfa as gcc emits
+        assert_eq!(local_address(None, offset.clone()), Ok(CFA - 20));
+        assert_eq!(
+            local_address(Some(expression(&[0x9c])), offset.clone()),
+            Ok(CFA - 20)
+        );
+        // DW_OP_breg6 16: rbp + 16
+        let breg6 = expression(&[0x76, 0x10]);
+        assert_eq!(
+            local_address(Some(breg6), offset.clone()),
+            Ok(RBP as usize + 16 - 20)
+        );
+        // DW_OP_reg6: the value of rbp itself
+        let reg6 = expression(&[0x56]);
+        assert_eq!(
+            local_address(Some(reg6.clone()), offset),
+            Ok(RBP as usize - 20)
+        );
+        // DW_OP_fbreg -20 inside a longer expression asks for the frame base as it goes
+        let fbreg = expression(&[0x91, 0x6c]);
+        assert_eq!(local_address(Some(reg6), fbreg), Ok(RBP as usize - 20));
+        // A frame base that needs the frame base is an error, not endless recursion
+        let error = Err("<error evaluating location: frame base refers to itself>".to_string());
+        let fbreg = expression(&[0x91, 0x6c]);
+        let offset = Location::FramePointerOffset(-20);
+        assert_eq!(local_address(Some(fbreg.clone()), fbreg), error);
+        assert_eq!(local_address(Some(offset.clone()), offset), error);
+    }
+}
@@ -1,24 +1,57 @@
+mod breakpoint;
+mod coredump;
 mod debugger;
 mod debugger_command;
 mod inferior;
 mod dwarf_data;
+mod eval;
+mod expr;
 mod gimli_wrapper;
+mod location;
+mod signals;
+mod unwind;
+mod value;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::unistd::Pid;
 use std::env;
 
 fn main() { // cargo run samples/sleepy_print
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
+    // deet -p <pid> attaches to a running process, reading debug info from its executable
+    let pid = if args.len() == 3 && args[1] == "-p" {
+        args[2].parse::<i32>().ok().map(Pid::from_raw)
+    } else {
+        None
+    };
+    // deet <target> --core <core file> examines a process that crashed
+    let core = if args.len() == 4 && args[2] == "--core" {
+        Some(args[3].clone())
+    } else {
+        None
+    };
+    let target = match pid {
+        Some(pid) => format!("/proc/{}/exe", pid),
+        None if args.len() == 2 || core.is_some() => args[1].clone(),
+        None => {
+            println!("Usage: {} <target program>", args[0]);
+            println!("       {} -p <pid>", args[0]);
+            println!("       {} <target program> --core <core file>", args[0]);
+            std::process::exit(1);
+        }
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target);
+    if let Some(pid) = pid {
+        debugger.attach(pid);
+    }
+    if let Some(core) = core {
+        debugger.load_core(&core);
+    }
+    debugger.run();
 }
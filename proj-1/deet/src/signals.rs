@@ -0,0 +1,184 @@
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::ffi::CStr;
+
+/// What to do when the inferior receives a signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalPolicy {
+    /// Stop the inferior and return to the prompt.
+    pub stop: bool,
+    /// Say that the signal arrived, even if the inferior carries on.
+    pub print: bool,
+    /// Deliver the signal to the inferior when it resumes, rather than discarding it.
+    pub pass: bool,
+}
+
+/// One of the keywords accepted by `handle`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalAction {
+    Stop,
+    NoStop,
+    Print,
+    NoPrint,
+    Pass,
+    NoPass,
+}
+
+impl SignalAction {
+    pub fn from_keyword(keyword: &str) -> Option<SignalAction> {
+        match keyword {
+            "stop" => Some(SignalAction::Stop),
+            "nostop" => Some(SignalAction::NoStop),
+            "print" => Some(SignalAction::Print),
+            "noprint" => Some(SignalAction::NoPrint),
+            "pass" | "noignore" => Some(SignalAction::Pass),
+            "nopass" | "ignore" => Some(SignalAction::NoPass),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a signal name with or without the SIG prefix ("SIGUSR1", "usr1") or a signal number.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::iterator().find(|signal| *signal as i32 == number);
+    }
+    let name = name.to_uppercase();
+    if name.starts_with("SIG") {
+        name.parse().ok()
+    } else {
+        format!("SIG{}", name).parse().ok()
+    }
+}
+
+/// The system's description of a signal, e.g. "Segmentation fault" for SIGSEGV.
+pub fn describe(signal: Signal) -> String {
+    unsafe { CStr::from_ptr(libc::strsignal(signal as i32)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The policy for each signal, as set with `handle`. Defaults follow gdb: signals that programs
+/// routinely get and handle themselves (SIGCHLD, SIGALRM, ...) are passed on silently, SIGINT
+/// and SIGTRAP stop the inferior without being delivered, and the rest stop it and are
+/// delivered when it resumes.
+pub struct SignalTable {
+    policies: HashMap<Signal, SignalPolicy>,
+}
+
+impl SignalTable {
+    pub fn new() -> SignalTable {
+        let mut policies = HashMap::new();
+        for signal in Signal::iterator() {
+            let policy = match signal {
+                Signal::SIGALRM
+                | Signal::SIGCHLD
+                | Signal::SIGURG
+                | Signal::SIGIO
+                | Signal::SIGVTALRM
+                | Signal::SIGPROF
+                | Signal::SIGWINCH => SignalPolicy {
+                    stop: false,
+                    print: false,
+                    pass: true,
+                },
+                Signal::SIGINT | Signal::SIGTRAP => SignalPolicy {
+                    stop: true,
+                    print: true,
+                    pass: false,
+                },
+                _ => SignalPolicy {
+                    stop: true,
+                    print: true,
+                    pass: true,
+                },
+            };
+            policies.insert(signal, policy);
+        }
+        SignalTable { policies }
+    }
+
+    pub fn get(&self, signal: Signal) -> SignalPolicy {
+        self.policies[&signal]
+    }
+
+    /// Applies `handle` keywords to a signal. As in gdb, stopping implies printing, and not
+    /// printing implies not stopping.
+    pub fn update(&mut self, signal: Signal, actions: &[SignalAction]) {
+        let policy = self.policies.get_mut(&signal).unwrap();
+        for action in actions {
+            match action {
+                SignalAction::Stop => {
+                    policy.stop = true;
+                    policy.print = true;
+                }
+                SignalAction::NoStop => policy.stop = false,
+                SignalAction::Print => policy.print = true,
+                SignalAction::NoPrint => {
+                    policy.print = false;
+                    policy.stop = false;
+                }
+                SignalAction::Pass => policy.pass = true,
+                SignalAction::NoPass => policy.pass = false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGINT"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("INT"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("sigint"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("2"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("usr1"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("SIGNOPE"), None);
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("99"), None);
+    }
+
+    #[test]
+    fn test_default_policies() {
+        let table = SignalTable::new();
+        let policy = |stop, print, pass| SignalPolicy { stop, print, pass };
+        assert_eq!(table.get(Signal::SIGCHLD), policy(false, false, true));
+        assert_eq!(table.get(Signal::SIGALRM), policy(false, false, true));
+        assert_eq!(table.get(Signal::SIGINT), policy(true, true, false));
+        assert_eq!(table.get(Signal::SIGTRAP), policy(true, true, false));
+        assert_eq!(table.get(Signal::SIGSEGV), policy(true, true, true));
+        assert_eq!(table.get(Signal::SIGUSR1), policy(true, true, true));
+    }
+
+    #[test]
+    fn test_update_policy() {
+        let mut table = SignalTable::new();
+        // nostop leaves printing alone
+        table.update(Signal::SIGUSR1, &[SignalAction::NoStop]);
+        assert!(!table.get(Signal::SIGUSR1).stop);
+        assert!(table.get(Signal::SIGUSR1).print);
+        // noprint implies nostop
+        table.update(Signal::SIGUSR2, &[SignalAction::NoPrint]);
+        assert!(!table.get(Signal::SIGUSR2).stop);
+        assert!(!table.get(Signal::SIGUSR2).print);
+        // stop implies print
+        table.update(Signal::SIGCHLD, &[SignalAction::Stop]);
+        assert!(table.get(Signal::SIGCHLD).stop);
+        assert!(table.get(Signal::SIGCHLD).print);
+        // print leaves stopping alone
+        table.update(Signal::SIGALRM, &[SignalAction::Print]);
+        assert!(!table.get(Signal::SIGALRM).stop);
+        assert!(table.get(Signal::SIGALRM).print);
+        // Actions apply in order, and passing is independent of the rest
+        table.update(
+            Signal::SIGSEGV,
+            &[SignalAction::NoPrint, SignalAction::Stop],
+        );
+        table.update(Signal::SIGSEGV, &[SignalAction::NoPass]);
+        let segv = table.get(Signal::SIGSEGV);
+        assert!(segv.stop && segv.print && !segv.pass);
+    }
+}
@@ -0,0 +1,318 @@
+//! Stack unwinding driven by the call frame information (CFI) in `.eh_frame` and `.debug_frame`.
+//!
+//! CFI describes, for every instruction, how to find the canonical frame address (CFA: the value
+//! of rsp before the call instruction) and where the caller's registers were saved. Unlike walking
+//! the rbp chain, this works for code compiled without frame pointers, and for shared libraries
+//! such as libc that the inferior may be stopped in.
+
+use crate::inferior::{Inferior, Mapping};
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EndianSlice, LittleEndian, Register, RegisterRule,
+    UninitializedUnwindContext, UnwindSection, UnwindTableRow,
+};
+use libc::user_regs_struct;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::fs;
+
+/// Stop unwinding after this many frames, in case the stack is corrupted.
+const MAX_FRAMES: usize = 256;
+
+// DWARF register numbers for x86-64
+const RBX: Register = Register(3);
+const RBP: Register = Register(6);
+const RSP: Register = Register(7);
+const R12: Register = Register(12);
+const R13: Register = Register(13);
+const R14: Register = Register(14);
+const R15: Register = Register(15);
+const RETURN_ADDRESS: Register = Register(16);
+
+/// The registers that unwinding recovers for outer frames.
+const RECOVERED_REGISTERS: [&str; 8] = ["rip", "rsp", "rbx", "rbp", "r12", "r13", "r14", "r15"];
+
+/// A stack frame, innermost first.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    /// 0 for the innermost frame, 1 for its caller, and so on.
+    pub level: usize,
+    /// The registers as they are in this frame. Only rip, rsp and the callee-saved registers
+    /// (rbx, rbp, r12-r15) are recovered for outer frames; the rest are copied from frame 0, so
+    /// check `has_register` before using them.
+    pub regs: user_regs_struct,
+    /// The frame's canonical frame address, or None if it couldn't be worked out.
+    pub cfa: Option<usize>,
+}
+
+impl Frame {
+    pub fn pc(&self) -> usize {
+        self.regs.rip as usize
+    }
+
+    /// The address to look up debugging information for. In outer frames, rip is the return
+    /// address, which may already belong to the next line (or even the next function), so this
+    /// points back into the call instruction instead.
+    pub fn lookup_pc(&self) -> usize {
+        if self.level == 0 {
+            self.pc()
+        } else {
+            self.pc() - 1
+        }
+    }
+
+    /// The frame's canonical frame address (which gcc also uses as the frame base that locals'
+    /// offsets are relative to). Without CFI, this falls back to assuming that rbp is used as a
+    /// frame pointer.
+    pub fn base(&self) -> usize {
+        self.cfa.unwrap_or(self.regs.rbp as usize + 16)
+    }
+
+    /// Whether `regs` holds the value the named register has in this frame. Any other register
+    /// of an outer frame was overwritten by the calls since, and its value is lost.
+    pub fn has_register(&self, name: &str) -> bool {
+        self.level == 0 || RECOVERED_REGISTERS.contains(&name)
+    }
+}
+
+/// The call frame information of one executable or shared library mapped into the inferior.
+struct Module {
+    /// How far the module was moved from the addresses in its file (zero for non-PIE
+    /// executables).
+    bias: usize,
+    /// The address and contents of .eh_frame.
+    eh_frame: Option<(usize, Vec<u8>)>,
+    debug_frame: Option<Vec<u8>>,
+    text_addr: usize,
+}
+
+/// Returns the regions of the inferior's memory that files (the executable and shared libraries)
+/// are mapped into.
+fn file_mappings(inferior: &Inferior) -> Vec<Mapping> {
+    let mut mappings = inferior.mappings().unwrap_or_default();
+    mappings.retain(|mapping| mapping.path.starts_with('/'));
+    mappings
+}
+
+/// Reads the CFI sections out of an object file that is mapped at `base` (the address its first
+/// byte is mapped at).
+fn load_module(path: &str, base: usize) -> Option<Module> {
+    let file = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+    let object = object::File::parse(&*mmap).ok()?;
+    let section = |name: &str| {
+        object.section_by_name(name).map(|section| {
+            (
+                section.address() as usize,
+                section.uncompressed_data().into_owned(),
+            )
+        })
+    };
+    // The lowest segment is the one mapped at `base`
+    let first_segment = object
+        .segments()
+        .map(|segment| segment.address() as usize)
+        .min()
+        .unwrap_or(0);
+    let page_size = 0x1000;
+    Some(Module {
+        bias: base.wrapping_sub(first_segment & !(page_size - 1)),
+        eh_frame: section(".eh_frame"),
+        debug_frame: section(".debug_frame").map(|(_, data)| data),
+        text_addr: section(".text").map_or(0, |(addr, _)| addr),
+    })
+}
+
+type Row<'a> = UnwindTableRow<EndianSlice<'a, LittleEndian>>;
+
+impl Module {
+    /// Finds the CFI row for an address (relative to the module's file), trying .eh_frame first.
+    fn find_row(&self, addr: usize) -> Option<Row<'_>> {
+        let mut bases = BaseAddresses::default().set_text(self.text_addr as u64);
+        if let Some((eh_frame_addr, data)) = &self.eh_frame {
+            bases = bases.set_eh_frame(*eh_frame_addr as u64);
+            let eh_frame = EhFrame::new(data, LittleEndian);
+            let mut ctx = UninitializedUnwindContext::new();
+            let row = eh_frame.unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                addr as u64,
+                EhFrame::cie_from_offset,
+            );
+            if let Ok(row) = row {
+                return Some(row);
+            }
+        }
+        let debug_frame = DebugFrame::new(self.debug_frame.as_ref()?, LittleEndian);
+        let mut ctx = UninitializedUnwindContext::new();
+        debug_frame
+            .unwind_info_for_address(&bases, &mut ctx, addr as u64, DebugFrame::cie_from_offset)
+            .ok()
+    }
+}
+
+/// Returns a reference to the field of `regs` holding a DWARF register, for the registers that
+/// matter to unwinding.
+fn register_mut(regs: &mut user_regs_struct, register: Register) -> Option<&mut u64> {
+    Some(match register {
+        RBX => &mut regs.rbx,
+        RBP => &mut regs.rbp,
+        RSP => &mut regs.rsp,
+        R12 => &mut regs.r12,
+        R13 => &mut regs.r13,
+        R14 => &mut regs.r14,
+        R15 => &mut regs.r15,
+        RETURN_ADDRESS => &mut regs.rip,
+        _ => return None,
+    })
+}
+
+fn get_register(regs: &user_regs_struct, register: Register) -> Option<u64> {
+    let mut regs = *regs;
+    register_mut(&mut regs, register).map(|value| *value)
+}
+
+/// The CFI of the files mapped into an inferior, loaded as unwinding needs it. Getting it means
+/// reading the inferior's memory map and parsing each file, which is too slow to do every time a
+/// conditional breakpoint is hit, so it is kept until the inferior execs or is replaced.
+#[derive(Default)]
+pub struct ModuleCache {
+    maps: Vec<Mapping>,
+    /// Modules loaded so far, or None for files whose CFI couldn't be read.
+    modules: Vec<(String, Option<Module>)>,
+}
+
+impl ModuleCache {
+    pub fn new() -> ModuleCache {
+        ModuleCache::default()
+    }
+}
+
+struct Unwinder<'a> {
+    inferior: &'a Inferior,
+    cache: &'a mut ModuleCache,
+}
+
+impl<'a> Unwinder<'a> {
+    fn module_for(&mut self, addr: usize) -> Option<&Module> {
+        let cache = &mut *self.cache;
+        let find_path = |maps: &[Mapping]| {
+            maps.iter()
+                .find(|mapping| mapping.start <= addr && addr < mapping.end)
+                .map(|mapping| mapping.path.clone())
+        };
+        let path = match find_path(&cache.maps) {
+            Some(path) => path,
+            None => {
+                // Libraries may have been loaded since the map was read
+                cache.maps = file_mappings(self.inferior);
+                find_path(&cache.maps)?
+            }
+        };
+        let index = match cache.modules.iter().position(|(p, _)| *p == path) {
+            Some(index) => index,
+            None => {
+                let base = cache
+                    .maps
+                    .iter()
+                    .filter(|mapping| mapping.path == path && mapping.offset == 0)
+                    .map(|mapping| mapping.start)
+                    .min()?;
+                cache.modules.push((path.clone(), load_module(&path, base)));
+                cache.modules.len() - 1
+            }
+        };
+        cache.modules[index].1.as_ref()
+    }
+
+    /// Works out a frame's CFA and its caller's registers. Returns None for the outermost frame.
+    fn unwind(
+        &mut self,
+        regs: &user_regs_struct,
+        lookup_pc: usize,
+    ) -> (Option<usize>, Option<user_regs_struct>) {
+        let inferior = self.inferior;
+        let module = match self.module_for(lookup_pc) {
+            Some(module) => module,
+            None => return (None, None),
+        };
+        let row = match module.find_row(lookup_pc.wrapping_sub(module.bias)) {
+            Some(row) => row,
+            None => return (None, None),
+        };
+        let cfa = match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => {
+                match get_register(regs, *register) {
+                    Some(value) => (value as i64 + offset) as usize,
+                    None => return (None, None),
+                }
+            }
+            // Only used for PLT entries and signal trampolines
+            CfaRule::Expression(_) => return (None, None),
+        };
+
+        let mut caller = *regs;
+        caller.rsp = cfa as u64;
+        for register in [RBX, RBP, R12, R13, R14, R15, RETURN_ADDRESS].iter() {
+            let value = match row.register(*register) {
+                RegisterRule::Undefined if *register == RETURN_ADDRESS => {
+                    // Marks the outermost frame (e.g. _start)
+                    return (Some(cfa), None);
+                }
+                RegisterRule::Undefined | RegisterRule::SameValue => continue,
+                RegisterRule::Offset(offset) => {
+                    match inferior.read_word((cfa as i64 + offset) as usize) {
+                        Ok(value) => value as u64,
+                        Err(_) => return (Some(cfa), None),
+                    }
+                }
+                RegisterRule::ValOffset(offset) => (cfa as i64 + offset) as u64,
+                RegisterRule::Register(other) => match get_register(regs, other) {
+                    Some(value) => value,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            *register_mut(&mut caller, *register).unwrap() = value;
+        }
+        if caller.rip == 0 {
+            return (Some(cfa), None);
+        }
+        (Some(cfa), Some(caller))
+    }
+}
+
+fn unwind_frames(
+    inferior: &Inferior,
+    cache: &mut ModuleCache,
+    max_frames: usize,
+) -> Result<Vec<Frame>, nix::Error> {
+    let mut unwinder = Unwinder { inferior, cache };
+    let mut regs = inferior.get_registers()?;
+    let mut frames = Vec::new();
+    while frames.len() < max_frames {
+        let mut frame = Frame {
+            level: frames.len(),
+            regs,
+            cfa: None,
+        };
+        let (cfa, caller) = unwinder.unwind(&regs, frame.lookup_pc());
+        frame.cfa = cfa;
+        frames.push(frame);
+        match caller {
+            // The stack grows down, so callers' frames must be at higher addresses
+            Some(caller) if caller.rsp > regs.rsp => regs = caller,
+            _ => break,
+        }
+    }
+    Ok(frames)
+}
+
+/// Unwinds the inferior's stack, returning its frames innermost first. Frames that can't be
+/// unwound end the backtrace rather than producing garbage.
+pub fn backtrace(inferior: &Inferior, cache: &mut ModuleCache) -> Result<Vec<Frame>, nix::Error> {
+    unwind_frames(inferior, cache, MAX_FRAMES)
+}
+
+/// Returns just the innermost frame, which is all that evaluating a breakpoint condition needs.
+pub fn innermost_frame(inferior: &Inferior, cache: &mut ModuleCache) -> Result<Frame, nix::Error> {
+    Ok(unwind_frames(inferior, cache, 1)?.remove(0))
+}
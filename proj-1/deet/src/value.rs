@@ -0,0 +1,224 @@
+//! Formatting of values read out of the inferior, according to their DWARF types.
+
+use crate::dwarf_data::{DwarfData, Encoding, Type, TypeKind};
+use crate::inferior::Inferior;
+use std::convert::TryInto;
+
+/// Maximum number of characters printed for a `char *` string.
+pub const MAX_STRING_LEN: usize = 200;
+
+/// Reads a little-endian integer out of (up to 8) bytes.
+pub fn bytes_to_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+/// Reads a little-endian integer out of (up to 8) bytes, sign-extending it.
+pub fn bytes_to_i64(bytes: &[u8]) -> i64 {
+    let value = bytes_to_u64(bytes);
+    let bits = bytes.len().min(8) * 8;
+    if bits == 0 || bits == 64 {
+        value as i64
+    } else {
+        ((value << (64 - bits)) as i64) >> (64 - bits)
+    }
+}
+
+/// Formats a character the way gdb does: printable characters as themselves, everything else as
+/// an escape.
+pub fn format_char(c: u8) -> String {
+    match c {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\\' => "\\\\".to_string(),
+        b'\'' => "\\'".to_string(),
+        b'"' => "\\\"".to_string(),
+        0x20..=0x7e => (c as char).to_string(),
+        _ => format!("\\{:03o}", c),
+    }
+}
+
+fn format_base(encoding: Encoding, bytes: &[u8]) -> String {
+    match encoding {
+        Encoding::Float if bytes.len() == 4 => {
+            format!("{}", f32::from_le_bytes(bytes.try_into().unwrap()))
+        }
+        Encoding::Float if bytes.len() == 8 => {
+            format!("{}", f64::from_le_bytes(bytes.try_into().unwrap()))
+        }
+        Encoding::Boolean => (bytes_to_u64(bytes) != 0).to_string(),
+        Encoding::SignedChar if bytes.len() == 1 => {
+            format!("{} '{}'", bytes[0] as i8, format_char(bytes[0]))
+        }
+        Encoding::UnsignedChar if bytes.len() == 1 => {
+            format!("{} '{}'", bytes[0], format_char(bytes[0]))
+        }
+        Encoding::Unsigned if bytes.len() <= 8 => bytes_to_u64(bytes).to_string(),
+        Encoding::Signed if bytes.len() <= 8 => bytes_to_i64(bytes).to_string(),
+        _ => format_raw(bytes),
+    }
+}
+
+/// Formats an enum value as the name of its enumerator, or as a number if it has none (as happens
+/// with flags that have been or'd together).
+fn format_enum(values: &[(String, i64)], bytes: &[u8]) -> String {
+    let value = bytes_to_i64(bytes);
+    match values.iter().find(|(_, v)| *v == value) {
+        Some((name, _)) => name.clone(),
+        None => value.to_string(),
+    }
+}
+
+fn format_raw(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+    format!("{{{}}}", hex.join(", "))
+}
+
+/// Reads the bytes of a NUL-terminated string (without the NUL) out of the inferior, giving up
+/// after `max_len` bytes.
+pub fn read_c_string(inferior: &Inferior, addr: usize, max_len: usize) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut curr = addr;
+    while bytes.len() < max_len {
+        let chunk = inferior.read_bytes(curr, 8).ok()?;
+        for c in chunk {
+            if c == 0 || bytes.len() == max_len {
+                return Some(bytes);
+            }
+            bytes.push(c);
+        }
+        curr += 8;
+    }
+    Some(bytes)
+}
+
+/// Quotes a string, escaping it the way gdb does.
+pub fn format_string(bytes: &[u8]) -> String {
+    let s: String = bytes.iter().map(|c| format_char(*c)).collect();
+    format!("\"{}\"", s)
+}
+
+/// Reads a NUL-terminated string out of the inferior, giving up after MAX_STRING_LEN characters.
+fn read_string(inferior: &Inferior, addr: usize) -> Option<String> {
+    let bytes = read_c_string(inferior, addr, MAX_STRING_LEN)?;
+    if bytes.len() == MAX_STRING_LEN {
+        Some(format!("{}...", format_string(&bytes)))
+    } else {
+        Some(format_string(&bytes))
+    }
+}
+
+fn is_char_type(debug_data: &DwarfData, offset: usize) -> bool {
+    match debug_data.get_type(offset) {
+        Some(ty) => is_char(debug_data, ty),
+        None => false,
+    }
+}
+
+fn is_char(debug_data: &DwarfData, ty: &Type) -> bool {
+    match debug_data.strip_typedefs(ty).kind {
+        TypeKind::Base(Encoding::SignedChar) | TypeKind::Base(Encoding::UnsignedChar) => true,
+        _ => false,
+    }
+}
+
+/// Formats a pointer, given the name of its type and the type it points to (None for `void *`).
+/// `char *` pointers show the string they point to, and function pointers the function.
+pub fn format_pointer(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    type_name: &str,
+    target: Option<&Type>,
+    addr: usize,
+) -> String {
+    match target.map(|target| debug_data.strip_typedefs(target)) {
+        Some(target) if addr != 0 && is_char(debug_data, target) => {
+            match read_string(inferior, addr) {
+                Some(s) => format!("{:#x} {}", addr, s),
+                None => format!(
+                    "{:#x} <error: Cannot access memory at address {:#x}>",
+                    addr, addr
+                ),
+            }
+        }
+        Some(Type {
+            kind: TypeKind::Function(_),
+            ..
+        }) => match debug_data.get_function_containing(addr) {
+            Some(func) if func.address == addr => {
+                format!("({}) {:#x} <{}>", type_name, addr, func.name)
+            }
+            _ => format!("({}) {:#x}", type_name, addr),
+        },
+        _ => format!("({}) {:#x}", type_name, addr),
+    }
+}
+
+/// Formats the bytes of a value of the given type. Pointers to `char` are followed so that the
+/// string they point to can be shown as well.
+pub fn format_value(
+    debug_data: &DwarfData,
+    inferior: &Inferior,
+    ty: &Type,
+    bytes: &[u8],
+) -> String {
+    // Pointers are described by their own name (e.g. `node_t *`), everything else by what the
+    // typedefs and qualifiers resolve to
+    let resolved = debug_data.strip_typedefs(ty);
+    match &resolved.kind {
+        TypeKind::Base(encoding) => format_base(*encoding, bytes),
+        TypeKind::Enum(values) => format_enum(values, bytes),
+        TypeKind::Pointer(target) => {
+            let target = target.and_then(|target| debug_data.get_type(target));
+            format_pointer(
+                debug_data,
+                inferior,
+                &ty.name,
+                target,
+                bytes_to_u64(bytes) as usize,
+            )
+        }
+        TypeKind::Typedef(None) | TypeKind::Const(None) | TypeKind::Volatile(None) => {
+            "void".to_string()
+        }
+        TypeKind::Typedef(_)
+        | TypeKind::Const(_)
+        | TypeKind::Volatile(_)
+        | TypeKind::Function(_) => format_raw(bytes),
+        TypeKind::Array(elem, len) => {
+            let elem_type = match debug_data.get_type(*elem) {
+                Some(elem_type) if elem_type.size > 0 => elem_type,
+                _ => return format_raw(bytes),
+            };
+            if is_char_type(debug_data, *elem) {
+                let end = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+                return format_string(&bytes[..end]);
+            }
+            let elems: Vec<String> = bytes
+                .chunks(elem_type.size)
+                .take(*len)
+                .map(|chunk| format_value(debug_data, inferior, elem_type, chunk))
+                .collect();
+            format!("{{{}}}", elems.join(", "))
+        }
+        TypeKind::Struct(members) => {
+            let fields: Vec<String> = members
+                .iter()
+                .map(|member| {
+                    let value = match debug_data.get_type(member.type_offset) {
+                        Some(member_type) if member.offset + member_type.size <= bytes.len() => {
+                            let member_bytes =
+                                &bytes[member.offset..member.offset + member_type.size];
+                            format_value(debug_data, inferior, member_type, member_bytes)
+                        }
+                        _ => "<unknown>".to_string(),
+                    };
+                    format!("{} = {}", member.name, value)
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+    }
+}